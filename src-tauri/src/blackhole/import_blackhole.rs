@@ -1,41 +1,71 @@
-use chrono::prelude::*;
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
+
+use crate::file_groups::group_files;
+use crate::journal::Transaction;
+use crate::placement::{self, ConflictPolicy, PlacementResult, Transfer};
+use crate::template::{PathTemplate, Sequence};
 
 pub async fn import_blackhole(
     files: Vec<String>,
     dest_path: String,
     cut: bool,
-) -> Result<(), String> {
+    template: Option<String>,
+    conflict: ConflictPolicy,
+    verify: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    let template = PathTemplate::or_default(template.as_deref())?;
     tauri::async_runtime::spawn_blocking(move || {
         do_import_blackhole(
             files.into_iter().map(PathBuf::from).collect(),
             PathBuf::from(dest_path),
             cut,
+            template,
+            conflict,
+            verify,
         )
     })
     .await
     .map_err(|e| e.to_string())?
 }
 
-fn do_import_blackhole(files: Vec<PathBuf>, dest: PathBuf, cut: bool) -> Result<(), String> {
-    for path in files {
-        let metadata = fs::metadata(&path).map_err(|e| e.to_string())?;
-        let mtime = metadata.modified().map_err(|e| e.to_string())?;
-        let datetime: DateTime<Local> = mtime.into();
-        let year = datetime.format("%Y").to_string();
-        let day = datetime.format("%Y-%m-%d").to_string();
-        let target_dir = dest.join(&year).join(&day);
-        fs::create_dir_all(&target_dir).map_err(|e| e.to_string())?;
-        let file_name = path
-            .file_name()
-            .ok_or_else(|| "Invalid filename".to_string())?;
-        let target = target_dir.join(file_name);
-        if !target.exists() {
-            fs::copy(&path, &target).map_err(|e| e.to_string())?;
-            if cut {
-                let _ = fs::remove_file(&path);
-            }
+fn do_import_blackhole(
+    files: Vec<PathBuf>,
+    dest: PathBuf,
+    cut: bool,
+    template: PathTemplate,
+    conflict: ConflictPolicy,
+    verify: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    let transaction = Transaction::begin("import_blackhole", dest.display().to_string());
+    let transfer = if cut {
+        Transfer::CopyAndRemove
+    } else {
+        Transfer::Copy
+    };
+    let mut sequence = Sequence::default();
+    let mut results = Vec::new();
+    for group in group_files(files) {
+        let target = template
+            .render(&group.primary, &mut sequence)
+            .map(|target| dest.join(target))
+            .and_then(|target| {
+                if let Some(target_dir) = target.parent() {
+                    transaction.create_dir_all(target_dir)?;
+                }
+                Ok(target)
+            });
+        match target {
+            Ok(target) => results.extend(placement::place_group(
+                &transaction,
+                &group,
+                &target,
+                conflict,
+                transfer,
+                verify,
+                &|_| {},
+            )),
+            Err(e) => results.extend(group.files().map(|file| PlacementResult::failed(file, &e))),
         }
     }
-    Ok(())
+    Ok(results)
 }
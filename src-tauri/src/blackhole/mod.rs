@@ -3,6 +3,8 @@ mod import_blackhole;
 
 pub use scan_blackhole_stream::BlackholeFolder;
 
+use crate::placement::{ConflictPolicy, PlacementResult};
+
 #[tauri::command]
 pub async fn scan_blackhole_stream(
     window: tauri::Window,
@@ -12,11 +14,25 @@ pub async fn scan_blackhole_stream(
     scan_blackhole_stream::scan_blackhole_stream(window, root_path, dest_path).await
 }
 
+/// Copies or, with `cut`, moves `files` to where `template` puts them below
+/// `dest_path`, and reports what was done with each of them. With `verify`
+/// set, every copy is checked against its source before the source is removed.
 #[tauri::command]
 pub async fn import_blackhole(
     files: Vec<String>,
     dest_path: String,
     cut: bool,
-) -> Result<(), String> {
-    import_blackhole::import_blackhole(files, dest_path, cut).await
+    template: Option<String>,
+    conflict: Option<ConflictPolicy>,
+    verify: Option<bool>,
+) -> Result<Vec<PlacementResult>, String> {
+    import_blackhole::import_blackhole(
+        files,
+        dest_path,
+        cut,
+        template,
+        conflict.unwrap_or_default(),
+        verify.unwrap_or(false),
+    )
+    .await
 }
@@ -3,7 +3,7 @@ use std::{collections::HashMap, path::PathBuf};
 use tauri::Emitter;
 use walkdir::WalkDir;
 
-use crate::file_formats::ALLOWED_EXTENSIONS;
+use crate::media_type::detect;
 
 #[derive(Serialize)]
 pub struct BlackholeFolder {
@@ -44,19 +44,12 @@ fn do_scan_blackhole_stream(
 
     for entry in entries {
         processed += 1.0;
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension().and_then(|s| s.to_str()) {
-                if ALLOWED_EXTENSIONS
-                    .iter()
-                    .any(|ok| ok.eq_ignore_ascii_case(ext))
-                {
-                    if let Some(parent) = entry.path().parent() {
-                        if !parent.starts_with(&dest) {
-                            map.entry(parent.to_path_buf())
-                                .or_default()
-                                .push(entry.path().display().to_string());
-                        }
-                    }
+        if entry.file_type().is_file() && detect(entry.path()).is_some() {
+            if let Some(parent) = entry.path().parent() {
+                if !parent.starts_with(&dest) {
+                    map.entry(parent.to_path_buf())
+                        .or_default()
+                        .push(entry.path().display().to_string());
                 }
             }
         }
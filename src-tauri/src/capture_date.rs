@@ -0,0 +1,311 @@
+//! When a photo or video was taken, from the most trustworthy source available.
+//! Copies, cloud syncs and FAT cards routinely change the modification time,
+//! so it is only the last resort.
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use crate::{exif, quicktime};
+
+/// XMP packets are embedded near the start of the file.
+const XMP_HEADER_BYTES: u64 = 1024 * 1024;
+
+/// XMP properties holding the capture time, most specific first.
+const XMP_DATE_PROPERTIES: [&str; 3] = [
+    "exif:DateTimeOriginal",
+    "photoshop:DateCreated",
+    "xmp:CreateDate",
+];
+
+/// Where a capture date was read from, in the order the sources are tried.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum DateSource {
+    ExifDateTimeOriginal,
+    ExifCreateDate,
+    /// The movie header of an MP4 or QuickTime clip
+    QuickTime,
+    Xmp,
+    FileName,
+    Modified,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureDate {
+    /// Wall-clock time where the photo was taken; folders are named after it
+    pub local: NaiveDateTime,
+    /// Offset from UTC, if the source records one
+    pub offset: Option<FixedOffset>,
+    pub source: DateSource,
+}
+
+/// Reads the capture date of a file. Only fails if not even the modification
+/// time can be read.
+pub fn read(path: &Path) -> io::Result<CaptureDate> {
+    if let Some(date) = from_exif(path) {
+        return Ok(date);
+    }
+    if let Some(date) = from_quicktime(path) {
+        return Ok(date);
+    }
+    if let Some(date) = from_xmp(path) {
+        return Ok(date);
+    }
+    if let Some(local) = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .and_then(from_file_name)
+    {
+        return Ok(CaptureDate {
+            local,
+            offset: None,
+            source: DateSource::FileName,
+        });
+    }
+    let modified: DateTime<Local> = path.metadata()?.modified()?.into();
+    Ok(CaptureDate {
+        local: modified.naive_local(),
+        offset: Some(*modified.offset()),
+        source: DateSource::Modified,
+    })
+}
+
+fn from_exif(path: &Path) -> Option<CaptureDate> {
+    let data = exif::read(path)?;
+    let candidates = [
+        (
+            exif::DATE_TIME_ORIGINAL,
+            exif::OFFSET_TIME_ORIGINAL,
+            DateSource::ExifDateTimeOriginal,
+        ),
+        (
+            exif::CREATE_DATE,
+            exif::OFFSET_TIME_DIGITIZED,
+            DateSource::ExifCreateDate,
+        ),
+    ];
+    candidates
+        .into_iter()
+        .find_map(|(tag, offset_tag, source)| {
+            // Unset dates are often stored as "0000:00:00 00:00:00", which does not parse
+            let local =
+                NaiveDateTime::parse_from_str(data.string(tag)?, "%Y:%m:%d %H:%M:%S").ok()?;
+            let offset = data
+                .string(offset_tag)
+                .or_else(|| data.string(exif::OFFSET_TIME))
+                .and_then(|s| s.parse().ok());
+            Some(CaptureDate {
+                local,
+                offset,
+                source,
+            })
+        })
+}
+
+/// Movie headers are in UTC, so the date is shifted to the local time zone of
+/// this computer, like the modification time.
+fn from_quicktime(path: &Path) -> Option<CaptureDate> {
+    let created: DateTime<Local> = quicktime::creation_time(path)?.into();
+    Some(CaptureDate {
+        local: created.naive_local(),
+        offset: Some(*created.offset()),
+        source: DateSource::QuickTime,
+    })
+}
+
+/// Looks for an XMP packet in the file itself, then in a sidecar next to it
+/// (`IMG_1.xmp` or `IMG_1.CR2.xmp`).
+fn from_xmp(path: &Path) -> Option<CaptureDate> {
+    let mut sidecar_with_extension = path.as_os_str().to_owned();
+    sidecar_with_extension.push(".xmp");
+    [
+        path.to_path_buf(),
+        path.with_extension("xmp"),
+        PathBuf::from(sidecar_with_extension),
+    ]
+    .iter()
+    .find_map(|file| {
+        let mut bytes = Vec::new();
+        File::open(file)
+            .ok()?
+            .take(XMP_HEADER_BYTES)
+            .read_to_end(&mut bytes)
+            .ok()?;
+        let text = String::from_utf8_lossy(&bytes);
+        let start = text.find("<x:xmpmeta")?;
+        let xmp = &text[start..];
+        XMP_DATE_PROPERTIES
+            .iter()
+            .find_map(|property| xmp_value(xmp, property))
+            .and_then(parse_iso_date)
+    })
+}
+
+/// The value of a property written either as an attribute, `name="value"`,
+/// or as an element, `<name>value</name>`.
+fn xmp_value<'a>(xmp: &'a str, property: &str) -> Option<&'a str> {
+    let attribute = format!("{}=\"", property);
+    if let Some(start) = xmp.find(&attribute).map(|i| i + attribute.len()) {
+        let end = xmp[start..].find('"')?;
+        return Some(&xmp[start..start + end]);
+    }
+    let element = format!("<{}>", property);
+    let start = xmp.find(&element)? + element.len();
+    let end = xmp[start..].find('<')?;
+    Some(&xmp[start..start + end])
+}
+
+/// Parses the ISO 8601 subset XMP uses: a date, optionally with time, seconds,
+/// fractions and an offset.
+fn parse_iso_date(value: &str) -> Option<CaptureDate> {
+    let value = value.trim();
+    let source = DateSource::Xmp;
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Some(CaptureDate {
+            local: date.naive_local(),
+            offset: Some(*date.offset()),
+            source,
+        });
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S%:z", "%Y-%m-%dT%H:%M%:z"] {
+        if let Ok(date) = DateTime::parse_from_str(value, format) {
+            return Some(CaptureDate {
+                local: date.naive_local(),
+                offset: Some(*date.offset()),
+                source,
+            });
+        }
+    }
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+    ] {
+        if let Ok(local) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(CaptureDate {
+                local,
+                offset: None,
+                source,
+            });
+        }
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    Some(CaptureDate {
+        local: date.and_time(NaiveTime::MIN),
+        offset: None,
+        source,
+    })
+}
+
+/// Finds a date like `20240101_123456`, `2024-01-01 12.34.56` or `20240101`
+/// in a file name such as `IMG_20240101_123456` or `IMG-20240101-WA0001`.
+fn from_file_name(name: &str) -> Option<NaiveDateTime> {
+    let bytes = name.as_bytes();
+    (0..bytes.len())
+        .filter(|&i| bytes[i].is_ascii_digit() && (i == 0 || !bytes[i - 1].is_ascii_digit()))
+        .find_map(|i| date_at(&bytes[i..]))
+}
+
+fn date_at(bytes: &[u8]) -> Option<NaiveDateTime> {
+    let mut cursor = Cursor { bytes, at: 0 };
+    let year = cursor.number(4)?;
+    let separator = cursor.separator();
+    let month = cursor.number(2)?;
+    if cursor.separator() != separator {
+        return None;
+    }
+    let day = cursor.number(2)?;
+    // Years outside this range are far more likely to be counters or ids
+    if !(1990..=2100).contains(&year) {
+        return None;
+    }
+    let date = NaiveDate::from_ymd_opt(year as i32, month, day)?;
+
+    let date_end = cursor.at;
+    let time = (|| {
+        cursor.separator();
+        let hour = cursor.number(2)?;
+        let separator = cursor.separator();
+        let minute = cursor.number(2)?;
+        if cursor.separator() != separator {
+            return None;
+        }
+        let second = cursor.number(2)?;
+        NaiveTime::from_hms_opt(hour, minute, second)
+    })();
+    // Digits that continue the date without forming a time make it a longer number
+    if time.is_none() && bytes.get(date_end).is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+    Some(date.and_time(time.unwrap_or(NaiveTime::MIN)))
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl Cursor<'_> {
+    fn number(&mut self, digits: usize) -> Option<u32> {
+        let text = self.bytes.get(self.at..self.at + digits)?;
+        if !text.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        self.at += digits;
+        std::str::from_utf8(text).ok()?.parse().ok()
+    }
+
+    /// Skips one of the separators used in file names and returns it.
+    fn separator(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.at)?;
+        matches!(byte, b'-' | b'_' | b'.' | b' ' | b'T').then(|| {
+            self.at += 1;
+            byte
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: (i32, u32, u32), time: (u32, u32, u32)) -> Option<NaiveDateTime> {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2)?.and_hms_opt(time.0, time.1, time.2)
+    }
+
+    #[test]
+    fn finds_dates_in_file_names() {
+        for (name, expected) in [
+            ("IMG_20240101_123456", at((2024, 1, 1), (12, 34, 56))),
+            ("2024-01-01 12.34.56", at((2024, 1, 1), (12, 34, 56))),
+            (
+                "Screenshot_2023-12-31-23-59-59",
+                at((2023, 12, 31), (23, 59, 59)),
+            ),
+            ("PXL_20240101T081500123", at((2024, 1, 1), (8, 15, 0))),
+            ("IMG-20240101-WA0001", at((2024, 1, 1), (0, 0, 0))),
+            ("20240229", at((2024, 2, 29), (0, 0, 0))),
+        ] {
+            assert_eq!(from_file_name(name), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn ignores_numbers_that_are_no_dates() {
+        for name in [
+            "IMG_0001",
+            "DSC01234",
+            // Inside a longer number, and too long to be a date itself
+            "123420240101",
+            "202401011",
+            "IMG_20241301",
+            "IMG_20230229",
+            "2024-01.01",
+            "18991231",
+        ] {
+            assert_eq!(from_file_name(name), None, "{}", name);
+        }
+    }
+}
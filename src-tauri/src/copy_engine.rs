@@ -0,0 +1,230 @@
+//! Copies files as fast as the devices allow: with `copy_file_range` where the
+//! kernel can, through a large buffer otherwise, counting the bytes as they go
+//! so imports can report throughput.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use sysinfo::{Disk, DiskKind, Disks};
+
+/// Bytes moved per step; small enough for smooth progress on slow cards.
+const CHUNK_BYTES: usize = 8 * 1024 * 1024;
+
+/// Copies `from` to `to` like `fs::copy`, calling `on_bytes` with every chunk.
+/// Unlike `fs::copy`, it never replaces a file: if `to` exists, whoever put it
+/// there since the caller looked, it fails with `AlreadyExists`. A copy that
+/// fails halfway is removed.
+pub fn copy_file(from: &Path, to: &Path, on_bytes: &dyn Fn(u64)) -> io::Result<u64> {
+    let mut source = File::open(from)?;
+    let metadata = source.metadata()?;
+    let mut target = OpenOptions::new().write(true).create_new(true).open(to)?;
+    let copied = match copy_range(&source, &target, on_bytes) {
+        Ok(Some(copied)) => Ok(copied),
+        Ok(None) => copy_buffered(&mut source, &mut target, on_bytes),
+        Err(e) => Err(e),
+    }
+    .and_then(|copied| {
+        target.set_permissions(metadata.permissions())?;
+        Ok(copied)
+    });
+    if copied.is_err() {
+        drop(target);
+        let _ = fs::remove_file(to);
+    }
+    copied
+}
+
+/// Lets the kernel copy, without the data passing through this process and,
+/// on file systems like btrfs or NFS, without it being copied at all. `None`
+/// if the two files do not support it.
+#[cfg(target_os = "linux")]
+fn copy_range(source: &File, target: &File, on_bytes: &dyn Fn(u64)) -> io::Result<Option<u64>> {
+    use std::os::fd::AsRawFd;
+
+    let mut copied = 0u64;
+    loop {
+        // SAFETY: both descriptors belong to open files for the whole call;
+        // null offsets make the kernel use and advance the file positions.
+        let n = unsafe {
+            libc::copy_file_range(
+                source.as_raw_fd(),
+                std::ptr::null_mut(),
+                target.as_raw_fd(),
+                std::ptr::null_mut(),
+                CHUNK_BYTES,
+                0,
+            )
+        };
+        if n < 0 {
+            let error = io::Error::last_os_error();
+            let unsupported = matches!(
+                error.raw_os_error(),
+                Some(libc::EXDEV | libc::ENOSYS | libc::EINVAL | libc::EOPNOTSUPP | libc::EPERM)
+            );
+            // Nothing was copied yet, so both file positions are still at the start
+            if unsupported && copied == 0 {
+                return Ok(None);
+            }
+            return Err(error);
+        }
+        if n == 0 {
+            return Ok(Some(copied));
+        }
+        copied += n as u64;
+        on_bytes(n as u64);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn copy_range(_: &File, _: &File, _: &dyn Fn(u64)) -> io::Result<Option<u64>> {
+    Ok(None)
+}
+
+fn copy_buffered(source: &mut File, target: &mut File, on_bytes: &dyn Fn(u64)) -> io::Result<u64> {
+    let mut buffer = vec![0u8; CHUNK_BYTES];
+    let mut copied = 0u64;
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(0) => return Ok(copied),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        target.write_all(&buffer[..read])?;
+        copied += read as u64;
+        on_bytes(read as u64);
+    }
+}
+
+/// Renames `from` to `to` like `fs::rename`, but like `copy_file` never
+/// replaces a file: if `to` exists, it fails with `AlreadyExists`.
+pub fn rename_file(from: &Path, to: &Path) -> io::Result<()> {
+    if rename_exclusive(from, to)? {
+        return Ok(());
+    }
+    link_and_remove(from, to)
+}
+
+/// Lets the kernel refuse to replace `to`. `false` if the file system does
+/// not support that.
+#[cfg(target_os = "linux")]
+fn rename_exclusive(from: &Path, to: &Path) -> io::Result<bool> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (from, to) = (c_path(from)?, c_path(to)?);
+    // SAFETY: both paths are NUL-terminated strings that live for the whole call.
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result == 0 {
+        return Ok(true);
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EINVAL | libc::ENOSYS) => Ok(false),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_exclusive(_: &Path, _: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+/// Creating a link fails if `to` exists, and removing `from` afterwards
+/// completes the move.
+fn link_and_remove(from: &Path, to: &Path) -> io::Result<()> {
+    fs::hard_link(from, to)?;
+    fs::remove_file(from).inspect_err(|_| {
+        let _ = fs::remove_file(to);
+    })
+}
+
+/// How many files to copy at once between `source` and `dest`. Each side is
+/// judged on its own and the slower one decides.
+pub fn workers(source: &Path, dest: &Path) -> usize {
+    let disks = Disks::new_with_refreshed_list();
+    let workers = |path: &Path| disk_of(&disks, path).map_or(2, device_workers);
+    workers(source).min(workers(dest))
+}
+
+/// Spinning disks lose more to seeking than parallel copies gain, and cards
+/// in a reader rarely serve more than two streams well; SSDs want a deep queue.
+fn device_workers(disk: &Disk) -> usize {
+    match disk.kind() {
+        DiskKind::HDD => 1,
+        _ if disk.is_removable() => 2,
+        DiskKind::SSD => 4,
+        _ => 2,
+    }
+}
+
+/// The disk `path` is on: the one with the longest mount point containing it.
+pub fn disk_of<'a>(disks: &'a Disks, path: &Path) -> Option<&'a Disk> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn copies_and_counts_every_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("a.jpg"), dir.path().join("b.jpg"));
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&from, &data).unwrap();
+        let counted = Cell::new(0);
+        let copied = copy_file(&from, &to, &|n| counted.set(counted.get() + n)).unwrap();
+        assert_eq!((copied, counted.get()), (100_000, 100_000));
+        assert_eq!(fs::read(&to).unwrap(), data);
+    }
+
+    #[test]
+    fn renames_never_replace_an_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("a.jpg"), dir.path().join("b.jpg"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "someone else's photo").unwrap();
+        for rename in [rename_file, link_and_remove] {
+            let error = rename(&from, &to).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+            assert_eq!(fs::read(&from).unwrap(), b"new");
+            assert_eq!(fs::read(&to).unwrap(), b"someone else's photo");
+        }
+
+        let free = dir.path().join("c.jpg");
+        rename_file(&from, &free).unwrap();
+        link_and_remove(&free, &from).unwrap();
+        assert!(!free.exists());
+        assert_eq!(fs::read(&from).unwrap(), b"new");
+    }
+
+    #[test]
+    fn never_replaces_an_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let (from, to) = (dir.path().join("a.jpg"), dir.path().join("b.jpg"));
+        fs::write(&from, "new").unwrap();
+        fs::write(&to, "someone else's photo").unwrap();
+        let error = copy_file(&from, &to, &|_| {}).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&to).unwrap(), b"someone else's photo");
+    }
+}
@@ -0,0 +1,78 @@
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Bytes taken from the start and from the end of a file for its partial hash.
+pub const PARTIAL_HASH_BYTES: u64 = 16 * 1024;
+
+/// Files at least this large are memory-mapped and hashed on all cores
+/// instead of being read into one big buffer.
+const MMAP_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+/// Hashes the first and the last `PARTIAL_HASH_BYTES` of a file. Two files of
+/// equal size with different partial hashes can never be identical.
+pub fn partial_hash(path: &Path, size: u64) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; PARTIAL_HASH_BYTES as usize];
+
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+    file.seek(SeekFrom::Start(size - PARTIAL_HASH_BYTES))?;
+    file.read_exact(&mut buffer)?;
+    hasher.update(&buffer);
+    Ok(hasher.finalize())
+}
+
+/// Computes the BLAKE3 hash of the whole file as a hex string.
+pub fn full_hash(path: &Path) -> io::Result<String> {
+    full_hash_with(path, |_, _| {})
+}
+
+/// Like `full_hash`, but files small enough to be read into one buffer are
+/// handed to `read` together with their hash, so a caller that needs their
+/// content as well does not read them a second time.
+pub fn full_hash_with(path: &Path, read: impl FnOnce(&[u8], &str)) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size >= MMAP_THRESHOLD {
+        // SAFETY: the mapping is only read while hashing. Truncating the file
+        // from another process during that time would fault; scanned archives
+        // are not expected to be rewritten under a running scan.
+        let map = unsafe { Mmap::map(&file)? };
+        let mut hasher = blake3::Hasher::new();
+        hasher.update_rayon(&map);
+        return Ok(hasher.finalize().to_hex().to_string());
+    }
+    let mut buffer = Vec::with_capacity(size as usize);
+    file.read_to_end(&mut buffer)?;
+    let hash = hash_bytes(&buffer);
+    read(&buffer, &hash);
+    Ok(hash)
+}
+
+pub fn hash_bytes(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+/// Compares two files byte by byte. Unlike equal hashes, this is proof.
+pub fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut buffer_a = vec![0u8; 256 * 1024];
+    let mut buffer_b = vec![0u8; 256 * 1024];
+    loop {
+        let read = a.read(&mut buffer_a)?;
+        if read == 0 {
+            // Equal lengths, so `b` is exhausted too unless it grew meanwhile
+            return Ok(b.read(&mut buffer_b[..1])? == 0);
+        }
+        b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
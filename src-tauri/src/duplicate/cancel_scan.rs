@@ -1,8 +1,61 @@
+use dashmap::DashMap;
 use once_cell::sync::Lazy;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-pub static CANCEL_SCAN: Lazy<AtomicBool> = Lazy::new(|| AtomicBool::new(false));
+/// Cooperative cancellation flag shared between a running scan and `cancel_scan`.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
 
-pub fn cancel_scan() {
-    CANCEL_SCAN.store(true, Ordering::Relaxed);
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Tokens of all running scans, keyed by scan id (the window label by default).
+static ACTIVE_SCANS: Lazy<DashMap<String, CancelToken>> = Lazy::new(DashMap::new);
+
+/// Keeps a scan registered for as long as it is alive and removes it on drop,
+/// so a finished or cancelled scan never affects the next one.
+pub struct ScanRegistration {
+    scan_id: String,
+    token: CancelToken,
+}
+
+impl ScanRegistration {
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for ScanRegistration {
+    fn drop(&mut self) {
+        ACTIVE_SCANS.remove_if(&self.scan_id, |_, token| Arc::ptr_eq(&token.0, &self.token.0));
+    }
+}
+
+/// Registers a fresh token for `scan_id`. A scan that is still running under
+/// the same id is cancelled, because its results would be overwritten anyway.
+pub fn register_scan(scan_id: String) -> ScanRegistration {
+    let token = CancelToken::default();
+    if let Some(previous) = ACTIVE_SCANS.insert(scan_id.clone(), token.clone()) {
+        previous.cancel();
+    }
+    ScanRegistration { scan_id, token }
+}
+
+/// Cancels the scan registered under `scan_id`. Returns `false` if no such scan is running.
+pub fn cancel_scan(scan_id: &str) -> bool {
+    match ACTIVE_SCANS.get(scan_id) {
+        Some(token) => {
+            token.cancel();
+            true
+        }
+        None => false,
+    }
 }
@@ -1,6 +1,277 @@
-pub fn delete_files(paths: Vec<String>) -> Result<(), String> {
-    for p in paths {
-        std::fs::remove_file(&p).map_err(|e| e.to_string())?;
+use dashmap::{DashMap, DashSet};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::scan_config::ScanRoot;
+use super::scan_folder_stream_multi::MatchPair;
+use crate::journal::{Operation, Transaction};
+
+/// Reference folders of the latest scan. Nothing below them is ever deleted,
+/// whatever the frontend asks for.
+static REFERENCE_ROOTS: Lazy<DashSet<PathBuf>> = Lazy::new(DashSet::new);
+
+/// The groups each file was found in by the latest scan that saw it.
+static GROUPS: Lazy<DashMap<PathBuf, Vec<Arc<Vec<PathBuf>>>>> = Lazy::new(DashMap::new);
+
+/// Protects the reference folders of a scan that is starting, in place of
+/// those of earlier scans, so a folder that is a normal root now can be
+/// cleaned up again.
+pub fn protect(roots: &[ScanRoot]) {
+    REFERENCE_ROOTS.clear();
+    for root in roots.iter().filter(|root| root.reference) {
+        REFERENCE_ROOTS.insert(root.path.clone());
+    }
+}
+
+/// Remembers the groups of a finished scan, so deleting can keep one copy of each.
+pub fn remember_groups(groups: &[MatchPair]) {
+    let mut by_file: HashMap<PathBuf, Vec<Arc<Vec<PathBuf>>>> = HashMap::new();
+    for group in groups {
+        let members: Arc<Vec<PathBuf>> =
+            Arc::new(group.files.iter().map(|f| PathBuf::from(&f.path)).collect());
+        for member in members.iter() {
+            by_file
+                .entry(member.clone())
+                .or_default()
+                .push(members.clone());
+        }
+    }
+    for (path, groups) in by_file {
+        GROUPS.insert(path, groups);
+    }
+}
+
+pub fn protected_root(path: &Path) -> Option<PathBuf> {
+    REFERENCE_ROOTS
+        .iter()
+        .find(|root| path.starts_with(root.key()))
+        .map(|root| root.key().clone())
+}
+
+/// Whether deleting `path` would leave one of its groups without any copy on disk.
+fn is_last_copy(path: &Path, deleted: &HashSet<PathBuf>) -> bool {
+    let Some(groups) = GROUPS.get(path) else {
+        return false;
+    };
+    groups.iter().any(|members| {
+        !members
+            .iter()
+            .any(|m| m != path && !deleted.contains(m) && m.symlink_metadata().is_ok())
+    })
+}
+
+/// What happened to one file of a `delete_files` request.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum DeleteOutcome {
+    Deleted,
+    NotFound,
+    PermissionDenied,
+    /// Another program holds the file open or locked
+    InUse,
+    /// The file lies in a reference folder
+    Protected,
+    /// The file is the last remaining copy of a duplicate group
+    LastInGroup,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeleteResult {
+    pub path: String,
+    pub outcome: DeleteOutcome,
+    /// Explains every outcome but `Deleted`, or why a deletion cannot be undone
+    pub message: Option<String>,
+}
+
+impl DeleteOutcome {
+    fn of(error: &io::Error) -> Self {
+        // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+        #[cfg(windows)]
+        if matches!(error.raw_os_error(), Some(32) | Some(33)) {
+            return DeleteOutcome::InUse;
+        }
+        match error.kind() {
+            ErrorKind::NotFound => DeleteOutcome::NotFound,
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                DeleteOutcome::PermissionDenied
+            }
+            ErrorKind::ResourceBusy | ErrorKind::ExecutableFileBusy => DeleteOutcome::InUse,
+            _ => DeleteOutcome::Failed,
+        }
+    }
+}
+
+/// Moves the files to the trash, or removes them for good if `permanent` is set.
+/// Every path is tried; the result tells for each one what happened. Unless
+/// `force` is set, the last copy of a duplicate group is left in place.
+pub fn delete_files(paths: Vec<String>, permanent: bool, force: bool) -> Vec<DeleteResult> {
+    let transaction =
+        Transaction::begin("delete_files", paths.first().cloned().unwrap_or_default());
+    let mut deleted = HashSet::new();
+    paths
+        .into_iter()
+        .map(|p| {
+            let path = PathBuf::from(&p);
+            let failed = |outcome, message| DeleteResult {
+                path: p.clone(),
+                outcome,
+                message: Some(message),
+            };
+            if let Some(root) = protected_root(&path) {
+                return failed(
+                    DeleteOutcome::Protected,
+                    format!(
+                        "'{}' lies in the reference folder '{}' and is never deleted",
+                        p,
+                        root.display()
+                    ),
+                );
+            }
+            if !force && is_last_copy(&path, &deleted) {
+                return failed(
+                    DeleteOutcome::LastInGroup,
+                    format!("'{}' is the last remaining copy of its group", p),
+                );
+            }
+            let result = if permanent {
+                std::fs::remove_file(&path).map(|_| Operation::Delete { path: path.clone() })
+            } else {
+                move_to_trash(&path)
+            };
+            match result {
+                Ok(operation) => {
+                    deleted.insert(path);
+                    // The file is gone either way; only the undo is lost
+                    let message = transaction
+                        .record(operation)
+                        .err()
+                        .map(|e| format!("deleted, but could not be recorded for undo: {}", e));
+                    DeleteResult {
+                        path: p,
+                        outcome: DeleteOutcome::Deleted,
+                        message,
+                    }
+                }
+                Err(e) => failed(DeleteOutcome::of(&e), e.to_string()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn move_to_trash(path: &Path) -> io::Result<Operation> {
+    super::trash::trash_file(path).map(|trashed| Operation::Trash {
+        path: trashed.original,
+        trashed: trashed.trashed,
+        info: trashed.info,
+    })
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub(crate) fn move_to_trash(path: &Path) -> io::Result<Operation> {
+    let path = std::path::absolute(path)?;
+    super::system_trash::trash_file(&path).map(|_| Operation::SystemTrash { path })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub(crate) fn move_to_trash(_path: &Path) -> io::Result<Operation> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "moving files to the trash is not supported on this system",
+    ))
+}
+
+/// What happened to one file of a `restore_files` request.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum RestoreOutcome {
+    Restored,
+    /// The file is not in the trash, e.g. because it was emptied
+    NotInTrash,
+    /// Another file took its place since
+    AlreadyExists,
+    /// The system does not let programs put files back, like macOS
+    Unsupported,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreResult {
+    pub path: String,
+    pub outcome: RestoreOutcome,
+    /// Explains every outcome but `Restored`
+    pub message: Option<String>,
+}
+
+/// Puts trashed files back at their original paths. Every path is tried; the
+/// result tells for each one what happened.
+pub fn restore_files(paths: Vec<String>) -> Vec<RestoreResult> {
+    paths
+        .into_iter()
+        .map(|p| {
+            let (outcome, message) = match restore_from_trash(Path::new(&p)) {
+                Ok(()) => (RestoreOutcome::Restored, None),
+                Err(e) => {
+                    let outcome = match e.kind() {
+                        ErrorKind::NotFound => RestoreOutcome::NotInTrash,
+                        ErrorKind::AlreadyExists => RestoreOutcome::AlreadyExists,
+                        ErrorKind::Unsupported => RestoreOutcome::Unsupported,
+                        _ => RestoreOutcome::Failed,
+                    };
+                    (outcome, Some(e.to_string()))
+                }
+            };
+            RestoreResult {
+                path: p,
+                outcome,
+                message,
+            }
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn restore_from_trash(path: &Path) -> io::Result<()> {
+    super::trash::restore_file(path).map(|_| ())
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+pub(crate) fn restore_from_trash(path: &Path) -> io::Result<()> {
+    super::system_trash::restore_file(path)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+pub(crate) fn restore_from_trash(_path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "restoring files from the trash is not supported on this system",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reference_folders_are_only_protected_by_the_latest_scan() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("photo.jpg");
+        std::fs::write(&photo, "photo").unwrap();
+        let root = |reference| ScanRoot {
+            path: dir.path().to_path_buf(),
+            reference,
+        };
+        let delete = || delete_files(vec![photo.display().to_string()], true, false);
+
+        protect(&[root(true)]);
+        assert_eq!(delete()[0].outcome, DeleteOutcome::Protected);
+        assert!(photo.exists());
+
+        protect(&[root(false)]);
+        assert_eq!(delete()[0].outcome, DeleteOutcome::Deleted);
+        assert!(!photo.exists());
     }
-    Ok(())
 }
@@ -0,0 +1,270 @@
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tempfile::NamedTempFile;
+
+use super::perceptual_hash::{PerceptualHashes, Transform};
+
+/// Bumped whenever the meaning of a cached field changes; older caches are discarded.
+const CACHE_VERSION: u32 = 5;
+
+/// Identifies a file version. Any change in size, mtime or inode invalidates the entry.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_ns: u64,
+    pub inode: u64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &fs::Metadata) -> Self {
+        let modified_ns = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        FileStamp {
+            size: metadata.len(),
+            modified_ns,
+            inode: inode(metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+/// Hashes remembered for one file. The preview itself lives in the thumbnail
+/// directory; the entry only references it.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CacheEntry {
+    pub stamp: FileStamp,
+    pub byte_hash: Option<String>,
+    pub perceptual: PerceptualHashes,
+    pub orientation: Transform,
+    pub upright: Option<PerceptualHashes>,
+    pub dihedral: Option<Vec<PerceptualHashes>>,
+    pub thumbnail: Option<PathBuf>,
+    /// Whether the file was already decoded; `perceptual` is only meaningful then.
+    #[serde(default)]
+    pub decoded: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, CacheEntry>,
+}
+
+/// On-disk cache of per-file hashes, shared by every scan of the process.
+pub struct HashCache {
+    dir: PathBuf,
+    entries: DashMap<String, CacheEntry>,
+}
+
+pub static HASH_CACHE: Lazy<HashCache> = Lazy::new(|| HashCache::load(cache_dir()));
+
+fn cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("imagemami")
+}
+
+impl HashCache {
+    fn load(dir: PathBuf) -> Self {
+        // A missing, unreadable or outdated cache simply starts out empty
+        let entries = fs::read(dir.join("hash_cache.json"))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<CacheFile>(&bytes).ok())
+            .filter(|file| file.version == CACHE_VERSION)
+            .map(|file| file.entries.into_iter().collect())
+            .unwrap_or_default();
+        HashCache { dir, entries }
+    }
+
+    fn thumbnail_dir(&self) -> PathBuf {
+        self.dir.join("thumbnails")
+    }
+
+    /// Returns the cached entry for `path` if it still matches the file on disk.
+    pub fn get(&self, path: &Path, stamp: FileStamp) -> Option<CacheEntry> {
+        let entry = self.entries.get(path.to_string_lossy().as_ref())?;
+        (entry.stamp == stamp).then(|| entry.clone())
+    }
+
+    pub fn insert(&self, path: &Path, entry: CacheEntry) {
+        self.entries.insert(path.to_string_lossy().into_owned(), entry);
+    }
+
+    /// Where the thumbnail of `size` pixels for a content hash is stored.
+    pub fn thumbnail_path(&self, byte_hash: &str, size: u32) -> PathBuf {
+        self.thumbnail_dir().join(format!("{}_{}.webp", byte_hash, size))
+    }
+
+    /// Stores a WebP thumbnail under its content hash and size and returns its location.
+    pub fn store_thumbnail(&self, byte_hash: &str, size: u32, webp: &[u8]) -> Option<PathBuf> {
+        fs::create_dir_all(self.thumbnail_dir()).ok()?;
+        let path = self.thumbnail_path(byte_hash, size);
+        if !path.exists() {
+            fs::write(&path, webp).ok()?;
+        }
+        Some(path)
+    }
+
+    /// Drops entries below `root` of files that were deleted or moved away
+    /// since. Files the last scan did not see are looked up on disk, since a
+    /// scan with filters skips files that are still there. Thumbnails no other
+    /// entry uses go as well.
+    pub fn retain_seen(&self, root: &Path, seen: &[PathBuf]) {
+        let seen: HashSet<String> = seen
+            .iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        let mut thumbnails = HashSet::new();
+        self.entries.retain(|path, entry| {
+            let gone = Path::new(path).starts_with(root)
+                && !seen.contains(path)
+                && fs::symlink_metadata(path).is_err();
+            if gone {
+                thumbnails.extend(entry.thumbnail.clone());
+            }
+            !gone
+        });
+        for entry in self.entries.iter() {
+            if let Some(thumbnail) = &entry.thumbnail {
+                thumbnails.remove(thumbnail);
+            }
+        }
+        for thumbnail in thumbnails {
+            let _ = fs::remove_file(thumbnail);
+        }
+    }
+
+    /// Forgets all entries below `root`, or the whole cache if `root` is `None`.
+    /// Thumbnails no longer referenced by any entry are deleted as well.
+    pub fn invalidate(&self, root: Option<&Path>) -> Result<(), String> {
+        match root {
+            Some(root) => self.entries.retain(|path, _| !Path::new(path).starts_with(root)),
+            None => self.entries.clear(),
+        }
+        self.remove_orphaned_thumbnails();
+        self.save()
+    }
+
+    fn remove_orphaned_thumbnails(&self) {
+        let referenced: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .filter_map(|e| e.thumbnail.clone())
+            .collect();
+        if let Ok(dir) = fs::read_dir(self.thumbnail_dir()) {
+            for entry in dir.filter_map(Result::ok) {
+                if !referenced.contains(&entry.path()) {
+                    let _ = fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+
+    /// Writes the cache atomically so an interrupted save never corrupts it.
+    /// Every save writes a temporary file of its own, so scans finishing at
+    /// the same time never write into each other's file.
+    pub fn save(&self) -> Result<(), String> {
+        fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let file = CacheFile {
+            version: CACHE_VERSION,
+            entries: self
+                .entries
+                .iter()
+                .map(|e| (e.key().clone(), e.value().clone()))
+                .collect(),
+        };
+        let json = serde_json::to_vec(&file).map_err(|e| e.to_string())?;
+        let mut tmp = NamedTempFile::new_in(&self.dir).map_err(|e| e.to_string())?;
+        tmp.write_all(&json).map_err(|e| e.to_string())?;
+        tmp.persist(self.dir.join("hash_cache.json"))
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_running_at_once_leave_a_readable_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(dir.path().to_path_buf());
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    for _ in 0..20 {
+                        cache.save().unwrap();
+                    }
+                });
+            }
+        });
+        let files: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let bytes = fs::read(dir.path().join("hash_cache.json")).unwrap();
+        let file: CacheFile = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(file.version, CACHE_VERSION);
+    }
+
+    fn entry(thumbnail: Option<PathBuf>) -> CacheEntry {
+        CacheEntry {
+            stamp: FileStamp {
+                size: 0,
+                modified_ns: 0,
+                inode: 0,
+            },
+            byte_hash: None,
+            perceptual: PerceptualHashes::default(),
+            orientation: Transform::default(),
+            upright: None,
+            dihedral: None,
+            thumbnail,
+            decoded: false,
+        }
+    }
+
+    #[test]
+    fn files_left_out_by_a_filter_keep_their_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = HashCache::load(dir.path().join("cache"));
+        let root = dir.path().join("photos");
+        fs::create_dir_all(&root).unwrap();
+        let (seen, filtered, deleted) = (root.join("a.jpg"), root.join("b.png"), root.join("c.jpg"));
+        fs::write(&seen, "a").unwrap();
+        fs::write(&filtered, "b").unwrap();
+        let shared = cache.store_thumbnail("aaaa", 256, b"webp").unwrap();
+        let own = cache.store_thumbnail("cccc", 256, b"webp").unwrap();
+        cache.insert(&seen, entry(Some(shared.clone())));
+        cache.insert(&filtered, entry(None));
+        cache.insert(&deleted, entry(Some(own.clone())));
+        cache.insert(&root.join("d.jpg"), entry(Some(shared.clone())));
+
+        cache.retain_seen(&root, std::slice::from_ref(&seen));
+        let stamp = entry(None).stamp;
+        assert!(cache.get(&seen, stamp).is_some());
+        assert!(cache.get(&filtered, stamp).is_some());
+        assert!(cache.get(&deleted, stamp).is_none());
+        assert!(shared.exists());
+        assert!(!own.exists());
+    }
+}
@@ -0,0 +1,106 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::scan_folder_stream_multi::{FileInfo, MatchPair};
+use crate::exif;
+use crate::file_formats;
+
+/// A preference for which copy of a duplicate group to keep. Rules are applied
+/// in order; each one only breaks the ties left by the previous ones.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeeperRule {
+    /// Copies in a reference folder; always applied first when a group has one
+    ReferenceFolder,
+    HighestResolution,
+    LargestFile,
+    OldestModified,
+    /// Copies below the given folder
+    PathPrefix { prefix: PathBuf },
+    RawOverJpeg,
+    /// The copy with the most EXIF tags, i.e. the one no tool has stripped
+    MostMetadata,
+}
+
+/// The file suggested to keep and the rule that singled it out.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeeperSuggestion {
+    pub path: String,
+    /// `None` if all rules tied and the first file was taken
+    pub rule: Option<KeeperRule>,
+}
+
+impl KeeperRule {
+    /// Scores a file under this rule; higher is better.
+    fn score(&self, file: &FileInfo) -> u64 {
+        let path = Path::new(&file.path);
+        match self {
+            KeeperRule::ReferenceFolder => file.reference as u64,
+            KeeperRule::HighestResolution => image::image_dimensions(path)
+                .map(|(width, height)| width as u64 * height as u64)
+                .unwrap_or_default(),
+            KeeperRule::LargestFile => file.size,
+            KeeperRule::OldestModified => file.age,
+            KeeperRule::PathPrefix { prefix } => path.starts_with(prefix) as u64,
+            KeeperRule::RawOverJpeg => file_formats::is_raw(path) as u64,
+            KeeperRule::MostMetadata => exif::read(path)
+                .map(|exif| exif.tags.len() as u64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Rejects rule chains that cannot be applied.
+pub fn validate(rules: &[KeeperRule]) -> Result<(), String> {
+    for (i, rule) in rules.iter().enumerate() {
+        if rules[..i].contains(rule) {
+            return Err(format!("keeper rule {:?} is listed twice", rule));
+        }
+        if let KeeperRule::PathPrefix { prefix } = rule {
+            if prefix.as_os_str().is_empty() {
+                return Err("the path prefix of a keeper rule must not be empty".to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Runs the rule chain over a group. Returns `None` if there is nothing to
+/// decide by: no rules were given and the group has no reference files.
+pub fn suggest(files: &[FileInfo], rules: &[KeeperRule]) -> Option<KeeperSuggestion> {
+    let has_reference = files.iter().any(|f| f.reference);
+    if files.is_empty() || (rules.is_empty() && !has_reference) {
+        return None;
+    }
+    let reference = has_reference.then_some(&KeeperRule::ReferenceFolder);
+    let chain = reference.into_iter().chain(rules);
+
+    let mut candidates: Vec<&FileInfo> = files.iter().collect();
+    for rule in chain {
+        let scores: Vec<u64> = candidates.iter().map(|f| rule.score(f)).collect();
+        let best = scores.iter().copied().max().unwrap_or_default();
+        candidates = candidates
+            .into_iter()
+            .zip(scores)
+            .filter(|(_, score)| *score == best)
+            .map(|(f, _)| f)
+            .collect();
+        if candidates.len() == 1 {
+            return Some(KeeperSuggestion {
+                path: candidates[0].path.clone(),
+                rule: Some(rule.clone()),
+            });
+        }
+    }
+    Some(KeeperSuggestion {
+        path: candidates[0].path.clone(),
+        rule: None,
+    })
+}
+
+/// Fills in the suggested keeper of every group.
+pub fn suggest_all(groups: &mut [MatchPair], rules: &[KeeperRule]) {
+    groups
+        .par_iter_mut()
+        .for_each(|group| group.keeper = suggest(&group.files, rules));
+}
@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::byte_hash::same_content;
+use super::delete_files::protected_root;
+use crate::journal::{Operation, Transaction};
+
+/// How a duplicate is made to share the keeper's data.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Both paths name the same file; a change through one shows in the other
+    Hardlink,
+    /// A copy-on-write clone (btrfs, XFS): shares the blocks but stays a file of its own
+    Reflink,
+    /// Points to the keeper's path and breaks if the keeper is moved
+    Symlink,
+}
+
+/// The order kinds are tried in when the frontend names none.
+pub const DEFAULT_KINDS: [LinkKind; 3] = [LinkKind::Hardlink, LinkKind::Reflink, LinkKind::Symlink];
+
+/// What happened to one duplicate of a `link_duplicates` request.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum LinkOutcome {
+    Linked,
+    /// Already a hardlink of or a symlink to the keeper, nothing to do
+    AlreadyLinked,
+    /// The content differs from the keeper's, so the file was left alone
+    ContentMismatch,
+    NotFound,
+    /// The file lies in a reference folder
+    Protected,
+    /// None of the link kinds worked on this file system
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkResult {
+    pub path: String,
+    pub outcome: LinkOutcome,
+    /// The kind of link that replaced the file
+    pub kind: Option<LinkKind>,
+    /// Explains every outcome but `Linked`, or why a link cannot be undone
+    pub message: Option<String>,
+}
+
+/// Replaces every duplicate with a link to `keeper`, trying `kinds` in order.
+/// Each duplicate is compared with the keeper first, and the finished link is
+/// compared again before it takes the duplicate's place.
+pub fn link_duplicates(keeper: &str, paths: Vec<String>, kinds: &[LinkKind]) -> Vec<LinkResult> {
+    let transaction = Transaction::begin("link_duplicates", keeper);
+    let keeper = Path::new(keeper);
+    paths
+        .into_iter()
+        .map(|p| {
+            let result = |outcome, kind, message| LinkResult {
+                path: p.clone(),
+                outcome,
+                kind,
+                message,
+            };
+            let path = Path::new(&p);
+            if let Some(root) = protected_root(path) {
+                let message = format!(
+                    "'{}' lies in the reference folder '{}' and is never replaced",
+                    p,
+                    root.display()
+                );
+                return result(LinkOutcome::Protected, None, Some(message));
+            }
+            match same_file(keeper, path) {
+                Ok(true) => return result(LinkOutcome::AlreadyLinked, None, None),
+                Ok(false) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return result(LinkOutcome::NotFound, None, Some(e.to_string()))
+                }
+                Err(e) => return result(LinkOutcome::Failed, None, Some(e.to_string())),
+            }
+            match same_content(keeper, path) {
+                Ok(true) => {}
+                Ok(false) => {
+                    let message = format!("'{}' differs from '{}'", p, keeper.display());
+                    return result(LinkOutcome::ContentMismatch, None, Some(message));
+                }
+                Err(e) => return result(LinkOutcome::Failed, None, Some(e.to_string())),
+            }
+
+            let mut errors = Vec::new();
+            for &kind in kinds {
+                match replace_with_link(keeper, path, kind) {
+                    Ok(()) => {
+                        let operation = Operation::Link {
+                            path: path.to_path_buf(),
+                            keeper: keeper.to_path_buf(),
+                        };
+                        let message = transaction
+                            .record(operation)
+                            .err()
+                            .map(|e| format!("linked, but could not be recorded for undo: {}", e));
+                        return result(LinkOutcome::Linked, Some(kind), message);
+                    }
+                    Err(e) => errors.push(format!("{:?}: {}", kind, e)),
+                }
+            }
+            result(LinkOutcome::Failed, None, Some(errors.join("; ")))
+        })
+        .collect()
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (fs::metadata(a)?, fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    Ok(fs::canonicalize(a)? == fs::canonicalize(b)?)
+}
+
+/// Builds the link next to `path` under a temporary name, checks it and only
+/// then renames it over `path`, so a failure leaves the duplicate untouched.
+fn replace_with_link(keeper: &Path, path: &Path, kind: LinkKind) -> io::Result<()> {
+    let temp = temp_path(path)?;
+    // Left over if a previous attempt was interrupted
+    let _ = fs::remove_file(&temp);
+    let built = create_link(keeper, &temp, kind).and_then(|_| {
+        if same_content(&temp, path)? {
+            Ok(())
+        } else {
+            Err(io::Error::other(
+                "the link does not read back like the duplicate",
+            ))
+        }
+    });
+    let replaced = built.and_then(|_| {
+        if kind == LinkKind::Reflink {
+            // A clone is a file of its own and would otherwise get default permissions
+            fs::set_permissions(&temp, fs::metadata(path)?.permissions())?;
+        }
+        fs::rename(&temp, path)
+    });
+    if replaced.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    replaced
+}
+
+fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' has no file name", path.display()),
+        )
+    })?;
+    Ok(path.with_file_name(format!(".{}.link-tmp", name.to_string_lossy())))
+}
+
+fn create_link(keeper: &Path, link: &Path, kind: LinkKind) -> io::Result<()> {
+    match kind {
+        LinkKind::Hardlink => fs::hard_link(keeper, link),
+        LinkKind::Reflink => reflink(keeper, link),
+        LinkKind::Symlink => symlink(&std::path::absolute(keeper)?, link),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let source = fs::File::open(source)?;
+    let target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    // SAFETY: both descriptors are open for the whole call; FICLONE only reads them.
+    if unsafe { libc::ioctl(target.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "reflinks are not supported on this system",
+    ))
+}
+
+#[cfg(unix)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
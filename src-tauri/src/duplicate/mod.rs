@@ -1,41 +1,128 @@
 mod scan_folder_stream_multi;
+mod byte_hash;
 mod delete_files;
 mod cancel_scan;
+mod hash_cache;
+mod keeper;
+mod link_files;
+mod perceptual_groups;
+mod perceptual_hash;
+mod scan_config;
+#[cfg(any(target_os = "macos", target_os = "windows"))]
+mod system_trash;
+#[cfg(target_os = "linux")]
+mod trash;
 
-pub use scan_folder_stream_multi::{CompareMethod, DuplicateMatches, FileInfo, MatchPair, ScanConfig};
+pub use byte_hash::{full_hash, same_content};
+pub(crate) use delete_files::{move_to_trash, restore_from_trash};
+pub use delete_files::{DeleteOutcome, DeleteResult, RestoreOutcome, RestoreResult};
+pub use keeper::{KeeperRule, KeeperSuggestion};
+pub use link_files::{LinkKind, LinkOutcome, LinkResult};
+pub use perceptual_hash::{HashSize, OrientationMode, Transform};
+pub use scan_config::{CompareMethod, FileFilter, PreviewSettings, ScanConfig, ScanRoot};
+pub use scan_folder_stream_multi::{
+    DuplicateGroupEvent, DuplicateMatches, FileInfo, MatchPair, ScanOutcome, ScanStats,
+};
 
+/// Runs a duplicate scan. `scan_id` defaults to the window label, so scans
+/// started from different windows can be cancelled independently.
+/// With `stream_groups` set, groups are emitted as `duplicate_group` events while scanning.
 #[tauri::command]
 pub async fn scan_folder_stream_multi(
+    window: tauri::Window,
+    config: ScanConfig,
+    scan_id: Option<String>,
+) -> Result<ScanOutcome, String> {
+    config.validate()?;
+    delete_files::protect(&config.roots);
+    let outcome = run_scan(window, config, scan_id).await?;
+    if let ScanOutcome::Completed(matches) = &outcome {
+        delete_files::remember_groups(&matches.groups);
+    }
+    Ok(outcome)
+}
+
+/// Forgets the cached hashes below `path` and hashes every file there again.
+/// Runs like a scan without compare methods that reads every file completely,
+/// so it reports progress and can be cancelled.
+#[tauri::command]
+pub async fn rebuild_hash_cache(
     window: tauri::Window,
     path: String,
-    tags: Vec<String>,
-) -> Result<DuplicateMatches, String> {
-    let methods = tags
-        .into_iter()
-        .filter_map(|t| match t.as_str() {
-            "hash" => Some(CompareMethod::ByteHash),
-            "dhash" => Some(CompareMethod::PerceptualDHash { threshold: 5 }),
-            _ => None,
-        })
-        .collect();
+    scan_id: Option<String>,
+) -> Result<ScanOutcome, String> {
+    let root = std::path::PathBuf::from(path);
+    hash_cache::HASH_CACHE.invalidate(Some(&root))?;
     let config = ScanConfig {
-        root: std::path::PathBuf::from(path),
-        methods,
+        roots: vec![ScanRoot {
+            path: root,
+            reference: false,
+        }],
+        methods: Vec::new(),
+        stream_groups: false,
+        filter: FileFilter::default(),
+        preview: PreviewSettings::default(),
+        keeper_rules: Vec::new(),
+        rebuild: true,
     };
+    run_scan(window, config, scan_id).await
+}
+
+/// Drops the whole hash cache including all stored thumbnails.
+#[tauri::command]
+pub fn clear_hash_cache() -> Result<(), String> {
+    hash_cache::HASH_CACHE.invalidate(None)
+}
+
+async fn run_scan(
+    window: tauri::Window,
+    config: ScanConfig,
+    scan_id: Option<String>,
+) -> Result<ScanOutcome, String> {
+    let scan_id = scan_id.unwrap_or_else(|| window.label().to_string());
+    let registration = cancel_scan::register_scan(scan_id.clone());
 
     tauri::async_runtime::spawn_blocking(move || {
-        scan_folder_stream_multi::scan_folder_stream(window, config)
+        scan_folder_stream_multi::scan_folder_stream(window, config, registration.token(), scan_id)
     })
     .await
     .map_err(|e| e.to_string())?
 }
 
+/// Moves files to the trash; with `permanent` set they are removed for good instead.
+/// Reports per file what happened. The last copy of a duplicate group is only
+/// deleted with `force` set.
+#[tauri::command]
+pub fn delete_files(
+    paths: Vec<String>,
+    permanent: Option<bool>,
+    force: Option<bool>,
+) -> Vec<DeleteResult> {
+    delete_files::delete_files(paths, permanent.unwrap_or(false), force.unwrap_or(false))
+}
+
+/// Deduplicates in place: replaces each of `paths` with a link to `keeper`, so
+/// the file stays at every path but its data is stored once. `kinds` are tried
+/// in order and default to hardlink, reflink, symlink.
+#[tauri::command]
+pub fn link_duplicates(
+    keeper: String,
+    paths: Vec<String>,
+    kinds: Option<Vec<LinkKind>>,
+) -> Vec<LinkResult> {
+    let kinds = kinds.unwrap_or_else(|| link_files::DEFAULT_KINDS.to_vec());
+    link_files::link_duplicates(&keeper, paths, &kinds)
+}
+
+/// Puts files moved to the trash by `delete_files` back at their original paths.
+/// Reports per file what happened.
 #[tauri::command]
-pub fn delete_files(paths: Vec<String>) -> Result<(), String> {
-    delete_files::delete_files(paths)
+pub fn restore_files(paths: Vec<String>) -> Vec<RestoreResult> {
+    delete_files::restore_files(paths)
 }
 
 #[tauri::command]
-pub fn cancel_scan() {
-    cancel_scan::cancel_scan()
+pub fn cancel_scan(window: tauri::Window, scan_id: Option<String>) -> bool {
+    let scan_id = scan_id.unwrap_or_else(|| window.label().to_string());
+    cancel_scan::cancel_scan(&scan_id)
 }
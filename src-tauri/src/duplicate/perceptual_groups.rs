@@ -0,0 +1,372 @@
+use rayon::prelude::*;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+
+use super::cancel_scan::CancelToken;
+use super::perceptual_hash::{ImageHash, Transform};
+
+/// BK-tree over perceptual hashes with the Hamming distance as metric.
+/// A query for radius `r` only descends into children whose edge distance
+/// lies within `r` of the distance to the current node.
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: ImageHash,
+    /// Indices of all entries carrying exactly this hash
+    items: Vec<usize>,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        BkTree { nodes: Vec::new() }
+    }
+
+    fn insert(&mut self, hash: ImageHash, item: usize) {
+        if self.nodes.is_empty() {
+            self.nodes.push(BkNode::new(hash, item));
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = self.nodes[current].hash.distance(hash);
+            if distance == 0 {
+                self.nodes[current].items.push(item);
+                return;
+            }
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(BkNode::new(hash, item));
+                    self.nodes[current].children.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Calls `found` for every item whose hash is within `radius` of `hash`.
+    fn query(&self, hash: ImageHash, radius: u32, mut found: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0];
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = node.hash.distance(hash);
+            if distance <= radius {
+                node.items.iter().copied().for_each(&mut found);
+            }
+            let low = distance.saturating_sub(radius);
+            let high = distance + radius;
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| (low..=high).contains(*d))
+                    .map(|(_, &child)| child),
+            );
+        }
+    }
+}
+
+impl BkNode {
+    fn new(hash: ImageHash, item: usize) -> Self {
+        BkNode {
+            hash,
+            items: vec![item],
+            children: HashMap::new(),
+        }
+    }
+}
+
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        UnionFind {
+            parent: (0..len).collect(),
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    /// Joins both sets; the smaller root wins so the result is order independent.
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
+
+/// A group of items linked by pairs of hashes within the threshold.
+pub struct Component {
+    /// Item indices, ascending
+    pub members: Vec<usize>,
+    /// For each member, the transform that makes it look like the first member
+    pub transforms: Vec<Transform>,
+    /// Largest Hamming distance of the links that joined the component
+    pub distance: u32,
+}
+
+/// Groups items that are connected through chains of pairs within `threshold`.
+///
+/// `hashes` holds `variants` hashes per item: item `i` as stored at
+/// `hashes[i * variants]`, followed by its other transforms in the order of
+/// `Transform::index`. Each item as stored is compared against every variant of
+/// all other items. Groups are ordered by their first member, so equal input
+/// gives equal output. Returns an empty list if the scan is cancelled meanwhile.
+pub fn connected_components(
+    hashes: &[ImageHash],
+    variants: usize,
+    threshold: u32,
+    cancel: &CancelToken,
+) -> Vec<Component> {
+    let items = hashes.len() / variants;
+    let mut tree = BkTree::new();
+    for (slot, &hash) in hashes.iter().enumerate() {
+        tree.insert(hash, slot);
+    }
+
+    // (i, j, t, distance): variant t of item j looks like item i
+    let edges: Vec<(usize, usize, usize, u32)> = (0..items)
+        .into_par_iter()
+        .filter(|_| !cancel.is_cancelled())
+        .flat_map_iter(|i| {
+            let hash = hashes[i * variants];
+            let mut neighbours = Vec::new();
+            tree.query(hash, threshold, |slot| {
+                let (j, t) = (slot / variants, slot % variants);
+                if j != i && (variants > 1 || j > i) {
+                    neighbours.push((i, j, t, hash.distance(hashes[slot])));
+                }
+            });
+            neighbours
+        })
+        .collect();
+    if cancel.is_cancelled() {
+        return Vec::new();
+    }
+
+    let mut sets = UnionFind::new(items);
+    for &(i, j, _, _) in &edges {
+        sets.union(i, j);
+    }
+
+    let mut components: HashMap<usize, Component> = HashMap::new();
+    for i in 0..items {
+        let root = sets.find(i);
+        components
+            .entry(root)
+            .or_insert_with(|| Component {
+                members: Vec::new(),
+                transforms: Vec::new(),
+                distance: 0,
+            })
+            .members
+            .push(i);
+    }
+    let mut links: HashMap<usize, Vec<(usize, Transform)>> = HashMap::new();
+    for &(i, j, t, distance) in &edges {
+        let root = sets.find(i);
+        if let Some(component) = components.get_mut(&root) {
+            component.distance = component.distance.max(distance);
+        }
+        if variants > 1 {
+            let t = Transform::from_index(t);
+            links.entry(i).or_default().push((j, t));
+            links.entry(j).or_default().push((i, t.inverse()));
+        }
+    }
+
+    let mut groups: Vec<Component> = components
+        .into_values()
+        .filter(|c| c.members.len() > 1)
+        .collect();
+    for group in &mut groups {
+        group.transforms = relative_transforms(&group.members, &links);
+    }
+    groups.sort_unstable_by_key(|c| c.members[0]);
+    groups
+}
+
+/// Walks the links breadth-first from the first member and composes the
+/// transforms along the way. A link `(j, t)` of `i` means `t(j)` looks like `i`.
+fn relative_transforms(
+    members: &[usize],
+    links: &HashMap<usize, Vec<(usize, Transform)>>,
+) -> Vec<Transform> {
+    let mut found: HashMap<usize, Transform> = HashMap::new();
+    found.insert(members[0], Transform::IDENTITY);
+    let mut queue = VecDeque::from([members[0]]);
+    while let Some(i) = queue.pop_front() {
+        let to_first = found[&i];
+        for &(j, t) in links.get(&i).into_iter().flatten() {
+            if let Entry::Vacant(slot) = found.entry(j) {
+                slot.insert(to_first.after(t));
+                queue.push_back(j);
+            }
+        }
+    }
+    members
+        .iter()
+        .map(|m| found.get(m).copied().unwrap_or_default())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reproducible pseudo-random words (xorshift64).
+    fn words(seed: u64) -> impl Iterator<Item = u64> {
+        let mut state = seed;
+        std::iter::repeat_with(move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+    }
+
+    /// Hashes in clusters of near neighbours, so queries of small radius
+    /// find something other than the hash itself.
+    fn clustered_hashes() -> Vec<ImageHash> {
+        let mut random = words(0x9e37_79b9_7f4a_7c15);
+        let mut hashes = Vec::new();
+        for _ in 0..20 {
+            let centre = random.next().unwrap();
+            for _ in 0..10 {
+                // Flips up to six random bits
+                let flips = (0..random.next().unwrap() % 7)
+                    .fold(0u64, |mask, _| mask | 1 << (random.next().unwrap() % 64));
+                hashes.push(ImageHash::from(centre ^ flips));
+            }
+        }
+        hashes
+    }
+
+    #[test]
+    fn bk_tree_finds_what_brute_force_finds() {
+        let hashes = clustered_hashes();
+        let mut tree = BkTree::new();
+        for (item, &hash) in hashes.iter().enumerate() {
+            tree.insert(hash, item);
+        }
+        for radius in [0, 1, 3, 6, 10, 20] {
+            for &query in &hashes {
+                let mut found = Vec::new();
+                tree.query(query, radius, |item| found.push(item));
+                found.sort_unstable();
+                let expected: Vec<usize> = (0..hashes.len())
+                    .filter(|&item| hashes[item].distance(query) <= radius)
+                    .collect();
+                assert_eq!(found, expected, "radius {}", radius);
+            }
+        }
+    }
+
+    #[test]
+    fn bk_tree_finds_every_item_of_a_hash() {
+        let mut tree = BkTree::new();
+        for item in 0..3 {
+            tree.insert(ImageHash::from(0b1010), item);
+        }
+        let mut found = Vec::new();
+        tree.query(ImageHash::from(0b1011), 1, |item| found.push(item));
+        found.sort_unstable();
+        assert_eq!(found, [0, 1, 2]);
+    }
+
+    #[test]
+    fn chains_of_close_hashes_form_one_group() {
+        let hashes = [
+            ImageHash::from(0),
+            // 3 bits from the first
+            ImageHash::from(0b111),
+            // 3 bits from the second, 6 from the first
+            ImageHash::from(0b111_000),
+            // Far from all others
+            ImageHash::from(u64::MAX),
+            // 2 bits from the far one
+            ImageHash::from(u64::MAX >> 2),
+        ];
+        let groups = connected_components(&hashes, 1, 4, &CancelToken::default());
+
+        let members: Vec<&[usize]> = groups.iter().map(|g| g.members.as_slice()).collect();
+        assert_eq!(members, [&[0, 1, 2][..], &[3, 4][..]]);
+        assert_eq!(groups[0].distance, 3);
+        assert_eq!(groups[1].distance, 2);
+    }
+
+    #[test]
+    fn hashes_beyond_the_threshold_stay_apart() {
+        let hashes = [
+            ImageHash::from(0),
+            ImageHash::from(0b11111),
+            ImageHash::from(0b11111 << 5),
+        ];
+        let groups = connected_components(&hashes, 1, 4, &CancelToken::default());
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn cancelled_grouping_returns_nothing() {
+        let hashes = [ImageHash::from(0), ImageHash::from(1)];
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        assert!(connected_components(&hashes, 1, 4, &cancel).is_empty());
+    }
+
+    #[test]
+    fn transforms_are_composed_along_the_chain() {
+        let turn = |rotation, mirrored| Transform { rotation, mirrored };
+        // Turned by 90 degrees, item 1 looks like item 0; mirrored, item 2
+        // looks like item 1
+        let links = HashMap::from([
+            (0, vec![(1, turn(90, false))]),
+            (1, vec![(0, turn(270, false)), (2, turn(0, true))]),
+            (2, vec![(1, turn(0, true))]),
+        ]);
+        assert_eq!(
+            relative_transforms(&[0, 1, 2], &links),
+            [turn(0, false), turn(90, false), turn(90, true)]
+        );
+        // Seen from item 1, item 0 has to be turned back
+        assert_eq!(
+            relative_transforms(&[1, 0, 2], &links),
+            [turn(0, false), turn(270, false), turn(0, true)]
+        );
+    }
+
+    #[test]
+    fn dihedral_variants_report_their_transform() {
+        let stored = ImageHash::from(0b1011_0110);
+        // At least 8 bits apart from each other and from `stored`
+        let other = |i: u64| ImageHash([0, 0xffff << (4 * i), 0, 0]);
+        // Item 1 looks like item 0 once variant 6 (mirrored, 180 degrees) is applied
+        let mut hashes = vec![stored];
+        hashes.extend((1..8).map(other));
+        hashes.extend((0..8).map(|t| if t == 6 { stored } else { other(t + 8) }));
+        let groups = connected_components(&hashes, 8, 2, &CancelToken::default());
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].members, [0, 1]);
+        assert_eq!(
+            groups[0].transforms,
+            [Transform::IDENTITY, Transform::from_index(6)]
+        );
+        assert_eq!(groups[0].distance, 0);
+    }
+}
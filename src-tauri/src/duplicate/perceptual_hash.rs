@@ -0,0 +1,578 @@
+use image::{imageops, imageops::FilterType, DynamicImage, GrayImage, metadata::Orientation};
+use serde::{Deserialize, Serialize};
+
+/// Perceptual fingerprints supported by the duplicate scan.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum PerceptualAlgorithm {
+    /// Difference hash: compares neighbouring pixels of a 9x8 thumbnail
+    Difference,
+    /// Average hash: compares each pixel of an 8x8 thumbnail with the mean
+    Average,
+    /// DCT hash: compares the lowest 8x8 frequencies of a 32x32 thumbnail with their median
+    Dct,
+    /// Wavelet hash: compares the Haar approximation band of a 64x64 thumbnail with its median
+    Wavelet,
+}
+
+impl PerceptualAlgorithm {
+    const ALL: [PerceptualAlgorithm; 4] = [
+        PerceptualAlgorithm::Difference,
+        PerceptualAlgorithm::Average,
+        PerceptualAlgorithm::Dct,
+        PerceptualAlgorithm::Wavelet,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            PerceptualAlgorithm::Difference => 0,
+            PerceptualAlgorithm::Average => 1,
+            PerceptualAlgorithm::Dct => 2,
+            PerceptualAlgorithm::Wavelet => 3,
+        }
+    }
+
+    fn compute(self, luma: &GrayImage, size: HashSize) -> ImageHash {
+        let side = size.side();
+        match self {
+            PerceptualAlgorithm::Difference => compute_dhash(luma, side),
+            PerceptualAlgorithm::Average => compute_ahash(luma, side),
+            PerceptualAlgorithm::Dct => compute_phash(luma, side),
+            PerceptualAlgorithm::Wavelet => compute_whash(luma, side),
+        }
+    }
+}
+
+/// Edge length of the bit grid of a perceptual hash, given as 8 or 16.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum HashSize {
+    /// 8x8 = 64 bits
+    #[default]
+    Small,
+    /// 16x16 = 256 bits, for telling apart near-identical shots of a series
+    Large,
+}
+
+impl HashSize {
+    fn side(self) -> usize {
+        match self {
+            HashSize::Small => 8,
+            HashSize::Large => 16,
+        }
+    }
+
+    pub fn bits(self) -> u32 {
+        (self.side() * self.side()) as u32
+    }
+}
+
+impl TryFrom<u32> for HashSize {
+    type Error = String;
+
+    fn try_from(side: u32) -> Result<Self, Self::Error> {
+        match side {
+            8 => Ok(HashSize::Small),
+            16 => Ok(HashSize::Large),
+            _ => Err(format!("unsupported hash size {}, expected 8 or 16", side)),
+        }
+    }
+}
+
+impl From<HashSize> for u32 {
+    fn from(size: HashSize) -> u32 {
+        size.side() as u32
+    }
+}
+
+/// A set of fingerprints, one per algorithm and hash size, so a scan only
+/// computes those its methods compare.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HashSelection(u8);
+
+impl HashSelection {
+    /// Every algorithm in both sizes
+    pub const ALL: HashSelection = HashSelection(u8::MAX);
+
+    pub fn with(self, algorithm: PerceptualAlgorithm, size: HashSize) -> HashSelection {
+        HashSelection(self.0 | Self::bit(algorithm, size))
+    }
+
+    pub fn union(self, other: HashSelection) -> HashSelection {
+        HashSelection(self.0 | other.0)
+    }
+
+    /// Whether every fingerprint of `other` is in this set.
+    pub fn includes(self, other: HashSelection) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    fn contains(self, algorithm: PerceptualAlgorithm, size: HashSize) -> bool {
+        self.0 & Self::bit(algorithm, size) != 0
+    }
+
+    fn iter(self) -> impl Iterator<Item = (PerceptualAlgorithm, HashSize)> {
+        [HashSize::Small, HashSize::Large]
+            .into_iter()
+            .flat_map(|size| PerceptualAlgorithm::ALL.map(|algorithm| (algorithm, size)))
+            .filter(move |&(algorithm, size)| self.contains(algorithm, size))
+    }
+
+    fn bit(algorithm: PerceptualAlgorithm, size: HashSize) -> u8 {
+        let first = match size {
+            HashSize::Small => 0,
+            HashSize::Large => 4,
+        };
+        1 << (first + algorithm.index())
+    }
+}
+
+/// The bits of a perceptual hash of up to 16x16 bits; 8x8 hashes only use the first word.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ImageHash(pub [u64; 4]);
+
+impl ImageHash {
+    pub fn distance(self, other: ImageHash) -> u32 {
+        self.0
+            .iter()
+            .zip(other.0)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+impl From<u64> for ImageHash {
+    fn from(bits: u64) -> Self {
+        ImageHash([bits, 0, 0, 0])
+    }
+}
+
+/// How a perceptual method deals with rotated or mirrored copies.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum OrientationMode {
+    /// Compare the pixels as stored in the file
+    #[default]
+    AsStored,
+    /// Turn every image upright according to its EXIF Orientation tag first
+    Exif,
+    /// Match any of the 8 rotations and mirror images of a file
+    Dihedral,
+}
+
+/// One of the 8 ways to rotate and mirror an image: first mirror it
+/// horizontally if `mirrored` is set, then rotate it clockwise by `rotation` degrees.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Transform {
+    pub rotation: u16,
+    pub mirrored: bool,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        rotation: 0,
+        mirrored: false,
+    };
+
+    /// All transforms, ordered by `index`.
+    pub fn all() -> impl Iterator<Item = Transform> {
+        (0..8).map(Transform::from_index)
+    }
+
+    pub fn index(self) -> usize {
+        (self.rotation / 90) as usize + if self.mirrored { 4 } else { 0 }
+    }
+
+    pub fn from_index(index: usize) -> Transform {
+        Transform {
+            rotation: (index % 4) as u16 * 90,
+            mirrored: index >= 4,
+        }
+    }
+
+    /// The transform that turns an image upright, as `DynamicImage::apply_orientation` would.
+    pub fn from_orientation(orientation: Orientation) -> Transform {
+        let (rotation, mirrored) = match orientation {
+            Orientation::NoTransforms => (0, false),
+            Orientation::Rotate90 => (90, false),
+            Orientation::Rotate180 => (180, false),
+            Orientation::Rotate270 => (270, false),
+            Orientation::FlipHorizontal => (0, true),
+            Orientation::FlipVertical => (180, true),
+            Orientation::Rotate90FlipH => (270, true),
+            Orientation::Rotate270FlipH => (90, true),
+        };
+        Transform { rotation, mirrored }
+    }
+
+    /// Applying `first` and then `self`.
+    pub fn after(self, first: Transform) -> Transform {
+        // Mirroring reverses the direction of a preceding rotation
+        let first_rotation = if self.mirrored {
+            (360 - first.rotation) % 360
+        } else {
+            first.rotation
+        };
+        Transform {
+            rotation: (self.rotation + first_rotation) % 360,
+            mirrored: self.mirrored != first.mirrored,
+        }
+    }
+
+    pub fn inverse(self) -> Transform {
+        if self.mirrored {
+            // Every mirrored transform is its own inverse
+            self
+        } else {
+            Transform {
+                rotation: (360 - self.rotation) % 360,
+                mirrored: false,
+            }
+        }
+    }
+
+    fn apply(self, img: &GrayImage) -> GrayImage {
+        let mirrored = if self.mirrored {
+            imageops::flip_horizontal(img)
+        } else {
+            img.clone()
+        };
+        match self.rotation {
+            90 => imageops::rotate90(&mirrored),
+            180 => imageops::rotate180(&mirrored),
+            270 => imageops::rotate270(&mirrored),
+            _ => mirrored,
+        }
+    }
+}
+
+/// The fingerprints of one image in one orientation, as far as the scans
+/// asked for them. They are computed together because decoding the image
+/// costs far more than any of the hashes. Missing ones are left out of the
+/// hash cache, which holds this up to ten times per file for dihedral methods.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct PerceptualHashes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dhash: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ahash: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub phash: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub whash: Option<u64>,
+    /// The same fingerprints on a 16x16 grid, ordered like `PerceptualAlgorithm`
+    #[serde(skip_serializing_if = "none_large")]
+    pub large: [Option<ImageHash>; 4],
+}
+
+fn none_large(large: &[Option<ImageHash>; 4]) -> bool {
+    large.iter().all(Option::is_none)
+}
+
+impl PerceptualHashes {
+    fn compute(luma: &GrayImage, selection: HashSelection) -> Self {
+        let mut hashes = PerceptualHashes::default();
+        for (algorithm, size) in selection.iter() {
+            let hash = algorithm.compute(luma, size);
+            match size {
+                HashSize::Small => {
+                    let small = match algorithm {
+                        PerceptualAlgorithm::Difference => &mut hashes.dhash,
+                        PerceptualAlgorithm::Average => &mut hashes.ahash,
+                        PerceptualAlgorithm::Dct => &mut hashes.phash,
+                        PerceptualAlgorithm::Wavelet => &mut hashes.whash,
+                    };
+                    *small = Some(hash.0[0]);
+                }
+                HashSize::Large => hashes.large[algorithm.index()] = Some(hash),
+            }
+        }
+        hashes
+    }
+
+    /// The fingerprints that are there.
+    pub fn selection(&self) -> HashSelection {
+        HashSelection::ALL
+            .iter()
+            .filter(|&(algorithm, size)| self.get(algorithm, size).is_some())
+            .fold(HashSelection::default(), |selection, (algorithm, size)| {
+                selection.with(algorithm, size)
+            })
+    }
+
+    pub fn get(&self, algorithm: PerceptualAlgorithm, size: HashSize) -> Option<ImageHash> {
+        match size {
+            HashSize::Small => match algorithm {
+                PerceptualAlgorithm::Difference => self.dhash,
+                PerceptualAlgorithm::Average => self.ahash,
+                PerceptualAlgorithm::Dct => self.phash,
+                PerceptualAlgorithm::Wavelet => self.whash,
+            }
+            .map(ImageHash::from),
+            HashSize::Large => self.large[algorithm.index()],
+        }
+    }
+}
+
+/// Fingerprints of a decoded image in the orientations a scan may compare.
+pub struct ImageFingerprints {
+    /// The pixels as stored
+    pub stored: PerceptualHashes,
+    /// After applying the EXIF orientation, if that is not the identity
+    pub upright: Option<PerceptualHashes>,
+    /// All 8 transforms, ordered by `Transform::index`
+    pub dihedral: Option<Vec<PerceptualHashes>>,
+}
+
+/// Computes the fingerprints of `selection` for `img` as stored, upright
+/// according to `orientation`, and, if requested, in all 8 transforms. Every
+/// hash works on a small grayscale copy, so the extra orientations cost next
+/// to nothing compared to decoding.
+pub fn fingerprint(
+    img: &DynamicImage,
+    orientation: Transform,
+    dihedral: bool,
+    selection: HashSelection,
+) -> ImageFingerprints {
+    // 256 pixels keep enough detail for the largest (128x128) hash input
+    let luma = img.resize(256, 256, FilterType::Triangle).to_luma8();
+    let dihedral = dihedral.then(|| {
+        Transform::all()
+            .map(|t| PerceptualHashes::compute(&t.apply(&luma), selection))
+            .collect::<Vec<_>>()
+    });
+    let stored = match &dihedral {
+        Some(all) => all[0],
+        None => PerceptualHashes::compute(&luma, selection),
+    };
+    let upright = (orientation != Transform::IDENTITY).then(|| match &dihedral {
+        Some(all) => all[orientation.index()],
+        None => PerceptualHashes::compute(&orientation.apply(&luma), selection),
+    });
+    ImageFingerprints {
+        stored,
+        upright,
+        dihedral,
+    }
+}
+
+/// Computes the dHash of a grayscale image on a `side` x `side` bit grid.
+fn compute_dhash(luma_img: &GrayImage, side: usize) -> ImageHash {
+    let width = side + 1;
+    let resized = image::imageops::resize(luma_img, width as u32, side as u32, FilterType::Triangle);
+    let pixels = resized.into_raw();
+
+    let mut bits = ImageHash::default();
+    for y in 0..side {
+        for x in 0..side {
+            let idx = y * width + x;
+            if pixels[idx] > pixels[idx + 1] {
+                let bit_pos = y * side + x;
+                bits.0[bit_pos / 64] |= 1 << (bit_pos % 64);
+            }
+        }
+    }
+    bits
+}
+
+fn compute_ahash(luma_img: &GrayImage, side: usize) -> ImageHash {
+    let resized = image::imageops::resize(luma_img, side as u32, side as u32, FilterType::Triangle);
+    let pixels: Vec<f32> = resized.into_raw().into_iter().map(f32::from).collect();
+    let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+    bits_above(&pixels, mean)
+}
+
+fn compute_phash(luma_img: &GrayImage, side: usize) -> ImageHash {
+    // The lowest `side` frequencies per axis of a 4x larger thumbnail
+    let size = 4 * side;
+    let low = side;
+    let resized = image::imageops::resize(luma_img, size as u32, size as u32, FilterType::Triangle);
+    let pixels: Vec<f32> = resized.into_raw().into_iter().map(f32::from).collect();
+
+    // Separable DCT-II, keeping only the low frequencies per axis
+    let cos: Vec<f32> = (0..low * size)
+        .map(|i| {
+            let (k, n) = (i / size, i % size);
+            (std::f32::consts::PI / size as f32 * (n as f32 + 0.5) * k as f32).cos()
+        })
+        .collect();
+    let mut rows = vec![0f32; size * low];
+    for y in 0..size {
+        for k in 0..low {
+            rows[y * low + k] = (0..size).map(|x| pixels[y * size + x] * cos[k * size + x]).sum();
+        }
+    }
+    let mut coefficients = vec![0f32; low * low];
+    for k in 0..low {
+        for u in 0..low {
+            coefficients[k * low + u] = (0..size).map(|y| rows[y * low + u] * cos[k * size + y]).sum();
+        }
+    }
+    bits_above(&coefficients, median(&coefficients))
+}
+
+fn compute_whash(luma_img: &GrayImage, side: usize) -> ImageHash {
+    let mut size = 8 * side;
+    let resized = image::imageops::resize(luma_img, size as u32, size as u32, FilterType::Triangle);
+    let mut band: Vec<f32> = resized.into_raw().into_iter().map(f32::from).collect();
+
+    // Three Haar decomposition levels shrink the approximation band to `side` x `side`
+    while size > side {
+        band = haar_approximation(&band, size);
+        size /= 2;
+    }
+    bits_above(&band, median(&band))
+}
+
+/// One level of the 2D Haar transform, keeping only the low-low band.
+fn haar_approximation(band: &[f32], size: usize) -> Vec<f32> {
+    let half = size / 2;
+    let mut out = vec![0f32; half * half];
+    for y in 0..half {
+        for x in 0..half {
+            let i = 2 * y * size + 2 * x;
+            out[y * half + x] = (band[i] + band[i + 1] + band[i + size] + band[i + size + 1]) / 2.0;
+        }
+    }
+    out
+}
+
+fn median(values: &[f32]) -> f32 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let mid = sorted.len() / 2;
+    (sorted[mid - 1] + sorted[mid]) / 2.0
+}
+
+fn bits_above(values: &[f32], pivot: f32) -> ImageHash {
+    values
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| **v > pivot)
+        .fold(ImageHash::default(), |mut bits, (i, _)| {
+            bits.0[i / 64] |= 1 << (i % 64);
+            bits
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    /// Left half black, right half white.
+    fn halves() -> GrayImage {
+        GrayImage::from_fn(64, 64, |x, _| Luma([if x < 32 { 0 } else { 255 }]))
+    }
+
+    /// Structure everywhere, so that no two neighbouring pixels are equal.
+    fn pattern() -> GrayImage {
+        GrayImage::from_fn(64, 64, |x, y| {
+            Luma([((x * 37 + y * 91 + x * y * 7) % 251) as u8])
+        })
+    }
+
+    fn inverted(img: &GrayImage) -> GrayImage {
+        let mut inverted = img.clone();
+        imageops::invert(&mut inverted);
+        inverted
+    }
+
+    #[test]
+    fn average_and_wavelet_hashes_of_halves() {
+        // The right four bits of every row
+        let right = ImageHash::from(0xf0f0_f0f0_f0f0_f0f0);
+        assert_eq!(compute_ahash(&halves(), 8), right);
+        assert_eq!(compute_whash(&halves(), 8), right);
+        assert_eq!(compute_ahash(&inverted(&halves()), 8), ImageHash::from(!0xf0f0_f0f0_f0f0_f0f0));
+    }
+
+    #[test]
+    fn dct_hash_of_halves_sees_the_edge() {
+        // Bit 0 is the average brightness, bit 1 the first horizontal
+        // frequency: negative with the dark half on the left. All others are
+        // close to the median of zero.
+        let hash = compute_phash(&halves(), 8);
+        assert_eq!(hash.0[0] & 0b11, 0b01);
+        assert_eq!(compute_phash(&inverted(&halves()), 8).0[0] & 0b11, 0b11);
+    }
+
+    #[test]
+    fn equal_images_have_equal_hashes() {
+        let img = pattern();
+        for size in [HashSize::Small, HashSize::Large] {
+            for algorithm in PerceptualAlgorithm::ALL {
+                let hash = algorithm.compute(&img, size);
+                assert_ne!(hash, ImageHash::default(), "{:?} {:?}", algorithm, size);
+                assert_eq!(hash.distance(algorithm.compute(&img.clone(), size)), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn inverted_images_are_far_apart() {
+        let (img, negative) = (pattern(), inverted(&pattern()));
+        for size in [HashSize::Small, HashSize::Large] {
+            for algorithm in PerceptualAlgorithm::ALL {
+                let distance = algorithm
+                    .compute(&img, size)
+                    .distance(algorithm.compute(&negative, size));
+                assert!(
+                    distance >= size.bits() * 7 / 8,
+                    "{:?} {:?}: only {} bits differ",
+                    algorithm,
+                    size,
+                    distance
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_selected_hashes_are_computed() {
+        let selection = HashSelection::default()
+            .with(PerceptualAlgorithm::Average, HashSize::Small)
+            .with(PerceptualAlgorithm::Wavelet, HashSize::Large);
+        let hashes = PerceptualHashes::compute(&pattern(), selection);
+        assert!(hashes.ahash.is_some() && hashes.large[3].is_some());
+        assert!(hashes.dhash.is_none() && hashes.phash.is_none() && hashes.whash.is_none());
+        assert_eq!(hashes.large.iter().flatten().count(), 1);
+        assert_eq!(hashes.selection(), selection);
+        assert!(HashSelection::ALL.includes(selection));
+        assert!(!selection.includes(HashSelection::ALL));
+    }
+
+    #[test]
+    fn composed_transforms_apply_one_after_the_other() {
+        // Not square, so a wrong rotation shows in the dimensions too
+        let img = GrayImage::from_fn(3, 2, |x, y| Luma([(x + 3 * y) as u8]));
+        for first in Transform::all() {
+            for then in Transform::all() {
+                assert_eq!(
+                    then.after(first).apply(&img),
+                    then.apply(&first.apply(&img)),
+                    "{:?} after {:?}",
+                    then,
+                    first
+                );
+            }
+            assert_eq!(first.inverse().after(first), Transform::IDENTITY);
+        }
+    }
+
+    #[test]
+    fn missing_hashes_are_not_stored() {
+        let hashes = PerceptualHashes {
+            ahash: Some(7),
+            ..PerceptualHashes::default()
+        };
+        let json = serde_json::to_string(&hashes).unwrap();
+        assert_eq!(json, r#"{"ahash":7}"#);
+        assert_eq!(serde_json::from_str::<PerceptualHashes>(&json).unwrap(), hashes);
+    }
+}
@@ -0,0 +1,274 @@
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use super::keeper::{self, KeeperRule};
+use super::perceptual_hash::{HashSize, OrientationMode, PerceptualAlgorithm};
+
+/// Everything a duplicate scan can be told, as sent by the frontend.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanConfig {
+    pub roots: Vec<ScanRoot>,
+    pub methods: Vec<CompareMethod>,
+    /// Emit `duplicate_group` events while the scan is still running.
+    #[serde(default)]
+    pub stream_groups: bool,
+    #[serde(default)]
+    pub filter: FileFilter,
+    #[serde(default)]
+    pub preview: PreviewSettings,
+    /// Chain of rules that pick the suggested keeper of each group
+    #[serde(default)]
+    pub keeper_rules: Vec<KeeperRule>,
+    /// Hash and decode every file, whatever the methods need, to fill the
+    /// hash cache. Only set by `rebuild_hash_cache`.
+    #[serde(skip)]
+    pub rebuild: bool,
+}
+
+/// One folder to scan.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScanRoot {
+    pub path: PathBuf,
+    /// Files in a reference folder are only compared against, e.g. the master
+    /// library when checking an incoming drive. They are never offered for deletion.
+    #[serde(default)]
+    pub reference: bool,
+}
+
+// FIXED: Added `Debug` to the derive macro to fix the compilation error.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum CompareMethod {
+    ByteHash,
+    PerceptualDHash {
+        threshold: u32,
+        #[serde(default)]
+        orientation: OrientationMode,
+        #[serde(default)]
+        hash_size: HashSize,
+    },
+    PerceptualAHash {
+        threshold: u32,
+        #[serde(default)]
+        orientation: OrientationMode,
+        #[serde(default)]
+        hash_size: HashSize,
+    },
+    PerceptualPHash {
+        threshold: u32,
+        #[serde(default)]
+        orientation: OrientationMode,
+        #[serde(default)]
+        hash_size: HashSize,
+    },
+    PerceptualWHash {
+        threshold: u32,
+        #[serde(default)]
+        orientation: OrientationMode,
+        #[serde(default)]
+        hash_size: HashSize,
+    },
+}
+
+/// The parameters shared by all perceptual compare methods.
+#[derive(Debug, Clone, Copy)]
+pub struct PerceptualParams {
+    pub algorithm: PerceptualAlgorithm,
+    /// Maximum Hamming distance between two linked images
+    pub threshold: u32,
+    pub orientation: OrientationMode,
+    pub hash_size: HashSize,
+}
+
+impl CompareMethod {
+    pub fn perceptual(&self) -> Option<PerceptualParams> {
+        let (algorithm, threshold, orientation, hash_size) = match *self {
+            CompareMethod::ByteHash => return None,
+            CompareMethod::PerceptualDHash { threshold, orientation, hash_size } => {
+                (PerceptualAlgorithm::Difference, threshold, orientation, hash_size)
+            }
+            CompareMethod::PerceptualAHash { threshold, orientation, hash_size } => {
+                (PerceptualAlgorithm::Average, threshold, orientation, hash_size)
+            }
+            CompareMethod::PerceptualPHash { threshold, orientation, hash_size } => {
+                (PerceptualAlgorithm::Dct, threshold, orientation, hash_size)
+            }
+            CompareMethod::PerceptualWHash { threshold, orientation, hash_size } => {
+                (PerceptualAlgorithm::Wavelet, threshold, orientation, hash_size)
+            }
+        };
+        Some(PerceptualParams {
+            algorithm,
+            threshold,
+            orientation,
+            hash_size,
+        })
+    }
+}
+
+/// Which files below the roots take part in a scan. Patterns are globs matched
+/// against the path relative to its root, e.g. `*.png` or `**/@eaDir/**`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileFilter {
+    /// Only files matching one of these are scanned; empty means all
+    pub include: Vec<String>,
+    /// Files and folders matching one of these are skipped
+    pub exclude: Vec<String>,
+    /// Skip files and folders whose name starts with a dot
+    pub skip_hidden: bool,
+    /// Files smaller than this many bytes are skipped
+    pub min_size: u64,
+}
+
+/// The compiled form of a `FileFilter`.
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    skip_hidden: bool,
+    pub min_size: u64,
+}
+
+impl FileFilter {
+    pub fn compile(&self) -> Result<PathFilter, String> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| Pattern::new(p).map_err(|e| format!("invalid pattern '{}': {}", p, e)))
+                .collect::<Result<Vec<_>, String>>()
+        };
+        Ok(PathFilter {
+            include: compile(&self.include)?,
+            exclude: compile(&self.exclude)?,
+            skip_hidden: self.skip_hidden,
+            min_size: self.min_size,
+        })
+    }
+}
+
+impl PathFilter {
+    const OPTIONS: MatchOptions = MatchOptions {
+        case_sensitive: false,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    };
+
+    /// Whether the walk should descend into or look at `path` at all.
+    pub fn admits(&self, root: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let hidden = relative
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with('.'));
+        if self.skip_hidden && hidden {
+            return false;
+        }
+        !self
+            .exclude
+            .iter()
+            .any(|p| p.matches_path_with(relative, Self::OPTIONS))
+    }
+
+    /// Whether a file that passed `admits` is scanned.
+    pub fn includes(&self, root: &Path, path: &Path) -> bool {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|p| p.matches_path_with(relative, Self::OPTIONS))
+    }
+}
+
+/// How previews for the review screen are made.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreviewSettings {
+    /// Without previews, byte hash scans never have to decode a file
+    pub enabled: bool,
+    /// Longest edge of a preview in pixels
+    pub size: u32,
+}
+
+impl Default for PreviewSettings {
+    fn default() -> Self {
+        PreviewSettings {
+            enabled: true,
+            size: 200,
+        }
+    }
+}
+
+const PREVIEW_SIZES: std::ops::RangeInclusive<u32> = 32..=1024;
+
+impl ScanConfig {
+    /// Rejects configurations that could never produce a meaningful scan.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.roots.is_empty() {
+            return Err("no folder to scan".to_string());
+        }
+        for (i, root) in self.roots.iter().enumerate() {
+            if !root.path.is_dir() {
+                return Err(format!("'{}' is not a folder", root.path.display()));
+            }
+            // A file must belong to exactly one root to know whether it is a reference
+            for other in &self.roots[..i] {
+                let (inner, outer) = if root.path.starts_with(&other.path) {
+                    (root, other)
+                } else if other.path.starts_with(&root.path) {
+                    (other, root)
+                } else {
+                    continue;
+                };
+                return Err(format!(
+                    "'{}' lies inside '{}', scanned folders must not overlap",
+                    inner.path.display(),
+                    outer.path.display()
+                ));
+            }
+        }
+        if self.roots.iter().all(|root| root.reference) {
+            return Err("at least one folder must not be a reference folder".to_string());
+        }
+        if self.methods.is_empty() {
+            return Err("no compare method selected".to_string());
+        }
+        for (i, method) in self.methods.iter().enumerate() {
+            if self.methods[..i].contains(method) {
+                return Err(format!("{:?} is listed twice", method));
+            }
+            if let Some(params) = method.perceptual() {
+                // Beyond half the bits, unrelated images match as often as not
+                let limit = params.hash_size.bits() / 2;
+                if params.threshold > limit {
+                    return Err(format!(
+                        "threshold {} of {:?} is too large, a {}-bit hash allows at most {}",
+                        params.threshold,
+                        params.algorithm,
+                        params.hash_size.bits(),
+                        limit
+                    ));
+                }
+            }
+        }
+        if self.preview.enabled && !PREVIEW_SIZES.contains(&self.preview.size) {
+            return Err(format!(
+                "preview size {} must lie between {} and {} pixels",
+                self.preview.size,
+                PREVIEW_SIZES.start(),
+                PREVIEW_SIZES.end()
+            ));
+        }
+        keeper::validate(&self.keeper_rules)?;
+        self.filter.compile().map(|_| ())
+    }
+
+    /// Whether `path` was found below a reference root.
+    pub fn is_reference(&self, path: &Path) -> bool {
+        self.roots
+            .iter()
+            .any(|root| root.reference && path.starts_with(&root.path))
+    }
+}
@@ -1,13 +1,24 @@
-use crate::file_formats::ALLOWED_EXTENSIONS;
+use super::byte_hash;
+use super::cancel_scan::CancelToken;
+use super::hash_cache::{CacheEntry, FileStamp, HASH_CACHE};
+use super::keeper::{self, KeeperRule, KeeperSuggestion};
+use super::perceptual_groups;
+use super::scan_config::{CompareMethod, PathFilter, ScanConfig, ScanRoot};
+use super::perceptual_hash::{
+    self, HashSelection, HashSize, ImageHash, OrientationMode, PerceptualAlgorithm,
+    PerceptualHashes, Transform,
+};
+use crate::media_type::{self, MediaType};
+use crate::{file_formats, heif, raw_preview};
 use base64::{engine::general_purpose, Engine as _};
-use blake3;
-use dashmap::DashMap;
-use image::ImageFormat;
+use image::metadata::Orientation;
+use image::{ImageDecoder, ImageFormat, ImageReader};
 // CORRECTED: Added ImageError for better error handling in preview generation
-use image::{imageops::FilterType, DynamicImage, ImageError};
+use image::{DynamicImage, ImageError};
 use rayon::prelude::*;
 use serde::Serialize;
-use std::io::{Cursor, Read};
+use std::collections::HashMap;
+use std::io::Cursor;
 use std::path::{Path, PathBuf};
 use std::time::{SystemTime, Instant};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -17,33 +28,54 @@ use walkdir::WalkDir;
 
 // --- Konfiguration und öffentliche Strukturen ---
 
-pub struct ScanConfig {
-    pub root: PathBuf,
-    pub methods: Vec<CompareMethod>,
-}
-
-// FIXED: Added `Debug` to the derive macro to fix the compilation error.
-#[derive(Debug, Clone, Serialize, PartialEq, Eq, Hash)]
-pub enum CompareMethod {
-    ByteHash,
-    PerceptualDHash { threshold: u32 },
-}
-
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct MatchPair {
     pub method: CompareMethod,
+    /// Largest Hamming distance among the links that formed a perceptual group
+    pub distance: Option<u32>,
     pub files: Vec<FileInfo>,
+    /// The file the keeper rules suggest to keep
+    pub keeper: Option<KeeperSuggestion>,
 }
 
 #[derive(Serialize)]
 pub struct DuplicateMatches {
     pub groups: Vec<MatchPair>,
+    pub stats: ScanStats,
+}
+
+/// Statistics of a scan; for a cancelled scan they describe the work done so far.
+#[derive(Serialize, Clone, Default)]
+pub struct ScanStats {
+    pub total: usize,
+    pub processed: usize,
+    pub groups: usize,
+    /// Files whose hashes were taken from the hash cache instead of being recomputed.
+    pub cache_hits: usize,
+    pub elapsed: f32,
+}
+
+/// Result of a duplicate scan. Serialized with a `status` tag so the frontend
+/// can tell a finished scan from one that was stopped by `cancel_scan`.
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum ScanOutcome {
+    Completed(DuplicateMatches),
+    Cancelled(ScanStats),
 }
 
 #[derive(Serialize, Clone)]
 pub struct FileInfo {
     pub hash: Option<String>,
     pub dhash: Option<String>,
+    pub ahash: Option<String>,
+    pub phash: Option<String>,
+    pub whash: Option<String>,
+    /// How this file has to be rotated or mirrored to look like the first file
+    /// of its group; only set by perceptual methods that handle orientation
+    pub transform: Option<Transform>,
+    /// Lies in a reference folder and must not be deleted
+    pub reference: bool,
     pub size: u64,
     pub path: String,
     pub age: u64,
@@ -51,8 +83,19 @@ pub struct FileInfo {
     pub preview: Option<String>,
 }
 
+/// Emitted as `duplicate_group` to the window that started the scan whenever a
+/// group is found or grows. Updates of the same group share the same `key`, so
+/// the frontend can replace it in place.
+#[derive(Serialize, Clone)]
+pub struct DuplicateGroupEvent {
+    pub scan_id: String,
+    pub key: String,
+    pub group: MatchPair,
+}
+
 #[derive(Serialize, Clone)]
 pub struct DuplicateProgress {
+    pub scan_id: String,
     pub processed: usize,
     pub total: usize,
     pub elapsed: f32,
@@ -66,50 +109,193 @@ struct FileMetaData {
     path: PathBuf,
     size: u64,
     modified: SystemTime,
+    stamp: FileStamp,
     byte_hash: Option<String>,
-    perceptual_hash: Option<u64>,
-    // Field for the generated preview string
-    preview_base64: Option<String>,
+    /// Fingerprints of the pixels as stored
+    perceptual: PerceptualHashes,
+    /// Transform that turns the image upright according to its EXIF orientation
+    orientation: Transform,
+    /// Fingerprints after applying `orientation`, if that is not the identity
+    upright: Option<PerceptualHashes>,
+    /// Fingerprints of all 8 transforms, only computed for dihedral methods
+    dihedral: Option<Vec<PerceptualHashes>>,
+    // Field for the generated preview string, shared so clones stay cheap
+    preview_base64: Option<Arc<str>>,
+    thumbnail: Option<PathBuf>,
+    /// Whether the file was decoded already, so non-images are not read twice
+    decoded: bool,
+    /// Found below a reference root
+    reference: bool,
+}
+
+/// What a scan needs from each decoded file.
+#[derive(Clone, Copy)]
+struct DecodeOptions {
+    /// Whether files are decoded at all; byte hashing alone reads them lazily
+    decode: bool,
+    /// Hashes of all 8 orientations, for dihedral methods
+    dihedral: bool,
+    /// The fingerprints the methods compare
+    hashes: HashSelection,
+    /// Longest edge of the preview, `None` if previews are disabled
+    preview: Option<u32>,
+    /// Also hash the files that are not decoded, like videos, to fill the cache
+    full_hash: bool,
+}
+
+impl DecodeOptions {
+    fn for_scan(config: &ScanConfig) -> Self {
+        DecodeOptions {
+            // Only perceptual methods need the decoded image, and rebuilding
+            // the cache needs everything
+            decode: config.rebuild
+                || config
+                    .methods
+                    .iter()
+                    .any(|m| *m != CompareMethod::ByteHash),
+            dihedral: config.methods.iter().any(|m| {
+                m.perceptual()
+                    .is_some_and(|p| p.orientation == OrientationMode::Dihedral)
+            }),
+            hashes: if config.rebuild {
+                HashSelection::ALL
+            } else {
+                config
+                    .methods
+                    .iter()
+                    .filter_map(CompareMethod::perceptual)
+                    .fold(HashSelection::default(), |hashes, p| {
+                        hashes.with(p.algorithm, p.hash_size)
+                    })
+            },
+            preview: config.preview.enabled.then_some(config.preview.size),
+            full_hash: config.rebuild,
+        }
+    }
+}
+
+impl FileMetaData {
+    fn needs_decode(&self, options: DecodeOptions) -> bool {
+        let is_image = self.perceptual != PerceptualHashes::default();
+        !self.decoded
+            || (is_image && !self.perceptual.selection().includes(options.hashes))
+            || (is_image && options.dihedral && self.dihedral.is_none())
+            || (is_image && options.preview.is_some() && self.preview_base64.is_none())
+    }
 }
 
 // --- Hauptlogik ---
 
-pub fn scan_folder_stream(window: Window, config: ScanConfig) -> Result<DuplicateMatches, String> {
+pub fn scan_folder_stream(
+    window: Window,
+    config: ScanConfig,
+    cancel: CancelToken,
+    scan_id: String,
+) -> Result<ScanOutcome, String> {
     let start = Instant::now();
-    let file_paths = find_allowed_files(&config.root);
+    let filter = config.filter.compile()?;
+    let file_paths = find_allowed_files(&config.roots, &filter, &cancel);
     let total = file_paths.len();
     let processed = Arc::new(AtomicUsize::new(0));
+    let cache_hits = AtomicUsize::new(0);
     let window = Arc::new(window);
 
-    let all_metadata: Vec<FileMetaData> = file_paths
-        .into_par_iter()
-        .filter_map(|path| {
+    let cancelled = |processed: usize, groups: usize| {
+        // Whatever was hashed so far is still worth keeping for the next scan
+        let _ = HASH_CACHE.save();
+        Ok(ScanOutcome::Cancelled(ScanStats {
+            total,
+            processed,
+            groups,
+            cache_hits: cache_hits.load(Ordering::SeqCst),
+            elapsed: start.elapsed().as_secs_f32(),
+        }))
+    };
+    if cancel.is_cancelled() {
+        return cancelled(0, 0);
+    }
+
+    let options = DecodeOptions::for_scan(&config);
+
+    let mut all_metadata: Vec<FileMetaData> = file_paths
+        .par_iter()
+        .map(|path| {
+            // Stop handing out work as soon as the scan is cancelled
+            if cancel.is_cancelled() {
+                return None;
+            }
             // Process file and keep track of progress
-            let meta = process_file_once(path.clone()).ok();
+            let meta = process_file_once(path.clone(), options, &cache_hits)
+                .ok()
+                .map(|meta| FileMetaData {
+                    reference: config.is_reference(path),
+                    ..meta
+                });
             let count = processed.fetch_add(1, Ordering::SeqCst) + 1;
             emit_progress(
                 &window,
+                &scan_id,
                 start,
                 count,
                 total,
                 path.display().to_string(),
             );
-            meta
+            Some(meta)
         })
+        .while_some()
+        .flatten()
         .collect();
 
+    if cancel.is_cancelled() {
+        return cancelled(processed.load(Ordering::SeqCst), 0);
+    }
+
+    for root in &config.roots {
+        HASH_CACHE.retain_seen(&root.path, &file_paths);
+    }
+
+    // Exact groups are the cheapest to confirm, so they are reported first
+    let mut methods = config.methods.clone();
+    methods.sort_by_key(|m| *m != CompareMethod::ByteHash);
+
     let mut groups = Vec::new();
 
-    for method in &config.methods {
+    for method in &methods {
         let mut matches = match method {
-            CompareMethod::ByteHash => find_duplicates_by_byte_hash(&all_metadata),
-            CompareMethod::PerceptualDHash { threshold } => {
-                find_duplicates_by_perceptual_hash(&all_metadata, *threshold)
+            CompareMethod::ByteHash => {
+                let on_group = |group: &MatchPair| {
+                    if config.stream_groups {
+                        let hash = group.files[0].hash.as_deref().unwrap_or_default();
+                        emit_group(&window, &scan_id, format!("hash:{}", hash), group.clone());
+                    }
+                };
+                find_duplicates_by_byte_hash(
+                    &mut all_metadata,
+                    options,
+                    &config.keeper_rules,
+                    &cancel,
+                    &on_group,
+                )
+            }
+            _ => {
+                let mut matches = find_duplicates_by_perceptual_hash(&all_metadata, method, &cancel);
+                keeper::suggest_all(&mut matches, &config.keeper_rules);
+                matches
             }
         };
+        if cancel.is_cancelled() {
+            return cancelled(processed.load(Ordering::SeqCst), groups.len());
+        }
+        // Byte hash groups were already streamed while they were confirmed
+        if config.stream_groups && *method != CompareMethod::ByteHash {
+            for (i, group) in matches.iter().enumerate() {
+                emit_group(&window, &scan_id, format!("{:?}#{}", method, i), group.clone());
+            }
+        }
         // After each method, emit progress to show the user something is happening
         emit_progress(
             &window,
+            &scan_id,
             start,
             processed.load(Ordering::SeqCst),
             total,
@@ -117,167 +303,590 @@ pub fn scan_folder_stream(window: Window, config: ScanConfig) -> Result<Duplicat
         );
         groups.append(&mut matches);
     }
-    
-    let result = DuplicateMatches { groups };
-    Ok(result)
+
+    // A cache that cannot be written only costs speed on the next scan
+    let _ = HASH_CACHE.save();
+
+    let stats = ScanStats {
+        total,
+        processed: processed.load(Ordering::SeqCst),
+        groups: groups.len(),
+        cache_hits: cache_hits.load(Ordering::SeqCst),
+        elapsed: start.elapsed().as_secs_f32(),
+    };
+    Ok(ScanOutcome::Completed(DuplicateMatches { groups, stats }))
 }
 
-fn find_allowed_files(root: &Path) -> Vec<PathBuf> {
-    WalkDir::new(root)
-        .into_iter()
+fn find_allowed_files(roots: &[ScanRoot], filter: &PathFilter, cancel: &CancelToken) -> Vec<PathBuf> {
+    roots
+        .iter()
+        .flat_map(|root| {
+            WalkDir::new(&root.path)
+                .into_iter()
+                .filter_entry(|e| e.depth() == 0 || filter.admits(&root.path, e.path()))
+                .map(move |e| (root, e))
+        })
+        .take_while(|_| !cancel.is_cancelled())
         .par_bridge()
-        .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_file())
+        .filter_map(|(root, e)| e.ok().map(|e| (root, e)))
+        .filter(|(root, e)| e.file_type().is_file() && filter.includes(&root.path, e.path()))
+        .map(|(_, e)| e)
+        .filter(|e| filter.min_size == 0 || e.metadata().is_ok_and(|m| m.len() >= filter.min_size))
         .map(|e| e.into_path())
-        .filter(|path| {
-            path.extension()
-                .and_then(|s| s.to_str())
-                .map_or(false, |ext| {
-                    ALLOWED_EXTENSIONS.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext))
-                })
-        })
+        .filter(|path| is_media_file(path))
         .collect()
 }
 
-/// Processes a single file to extract ALL necessary metadata,
-/// including hashes and the new image preview.
-fn process_file_once(path: PathBuf) -> Result<FileMetaData, std::io::Error> {
-    let file = std::fs::File::open(&path)?;
-    let metadata = file.metadata()?;
-    let size = metadata.len();
+/// Takes files with a media extension, whose content is checked when they are
+/// decoded, and leaves out those whose extension belongs to something else.
+/// Only the rest, like a photo without an extension or with an unknown one,
+/// is opened to sniff its type.
+fn is_media_file(path: &Path) -> bool {
+    if file_formats::is_media(path) {
+        return true;
+    }
+    !file_formats::is_other(path) && media_type::detect(path).is_some()
+}
+
+/// Collects the metadata of a single file. Unchanged files are served from the
+/// hash cache; otherwise the file is only read if `options.decode` asks for the
+/// perceptual hashes and preview. Byte hashes are computed later, on demand.
+fn process_file_once(
+    path: PathBuf,
+    options: DecodeOptions,
+    cache_hits: &AtomicUsize,
+) -> Result<FileMetaData, std::io::Error> {
+    let metadata = std::fs::metadata(&path)?;
     let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let stamp = FileStamp::from_metadata(&metadata);
+
+    let cached = HASH_CACHE
+        .get(&path, stamp)
+        .map(|entry| from_cache_entry(&path, modified, entry, options.preview));
+    let mut meta = match cached {
+        Some(meta) => {
+            if !options.decode || !meta.needs_decode(options) {
+                cache_hits.fetch_add(1, Ordering::Relaxed);
+            }
+            meta
+        }
+        None => FileMetaData {
+            path,
+            size: stamp.size,
+            modified,
+            stamp,
+            byte_hash: None,
+            perceptual: PerceptualHashes::default(),
+            orientation: Transform::IDENTITY,
+            upright: None,
+            dihedral: None,
+            preview_base64: None,
+            thumbnail: None,
+            decoded: false,
+            reference: false,
+        },
+    };
+    if options.decode {
+        decode_file(&mut meta, options)?;
+    }
+    Ok(meta)
+}
 
-    let mut buffer = Vec::with_capacity(size as usize);
-    let mut reader = std::io::BufReader::new(file);
-    reader.read_to_end(&mut buffer)?;
+/// Reads and decodes the file to fill in the perceptual hashes and, if enabled,
+/// the preview; for dihedral methods also the hashes of all rotations and
+/// mirror images. The byte hash comes for free since the whole file is in memory anyway.
+fn decode_file(meta: &mut FileMetaData, options: DecodeOptions) -> Result<(), std::io::Error> {
+    if !meta.needs_decode(options) {
+        return Ok(());
+    }
+    // Whatever the extension says, only images are decoded. Videos, and files
+    // that are no media at all, take part in byte hash comparisons only, which
+    // read them lazily
+    if media_type::detect(&meta.path).is_none_or(MediaType::is_video) {
+        if options.full_hash && meta.byte_hash.is_none() {
+            meta.byte_hash = Some(byte_hash::full_hash(&meta.path)?);
+            remember(meta);
+        }
+        return Ok(());
+    }
+    let buffer = std::fs::read(&meta.path)?;
+    let byte_hash = meta
+        .byte_hash
+        .get_or_insert_with(|| byte_hash::hash_bytes(&buffer))
+        .clone();
 
-    // 1. Calculate BLAKE3 hash (always)
-    let byte_hash = blake3::hash(&buffer).to_hex().to_string();
+    // Calculate perceptual hashes and generate preview ONLY for valid images
+    if let Some((img, orientation)) = decode_image(&buffer) {
+        // Fingerprints from earlier scans are computed again, so that all
+        // orientations always have the same ones. At least one is needed to
+        // tell later scans that the file is an image.
+        let mut hashes = options.hashes.union(meta.perceptual.selection());
+        if hashes == HashSelection::default() {
+            hashes = hashes.with(PerceptualAlgorithm::Difference, HashSize::Small);
+        }
+        let fingerprints = perceptual_hash::fingerprint(
+            &img,
+            orientation,
+            options.dihedral || meta.dihedral.is_some(),
+            hashes,
+        );
+        meta.perceptual = fingerprints.stored;
+        meta.upright = fingerprints.upright;
+        meta.dihedral = fingerprints.dihedral;
+        meta.orientation = orientation;
 
-    // 2. Calculate perceptual hash and generate preview ONLY for valid images
-    //    FIX: This block now correctly generates and stores the preview.
-    let (perceptual_hash, preview_base64) = if let Ok(img) = image::load_from_memory(&buffer) {
-        let p_hash = compute_dhash(&img).ok();
-        let preview = generate_preview_base64(&img).ok(); // Actually generate the preview
-        (p_hash, preview) // Return both the hash and the preview string
-    } else {
-        (None, None) // Not an image, so no p-hash or preview
-    };
+        if let Some(size) = options.preview {
+            let preview = make_preview(img, orientation, &byte_hash, size);
+            meta.thumbnail = preview.as_ref().and_then(|p| p.thumbnail.clone());
+            meta.preview_base64 = preview.map(|p| p.data_url);
+        }
+    }
+    meta.decoded = true;
+    remember(meta);
+    Ok(())
+}
+
+/// Decodes an image together with the transform its EXIF orientation asks for.
+/// RAW files are decoded from the largest of their embedded previews that
+/// decodes, turned by the orientation of the RAW file if it has one.
+fn decode_image(buffer: &[u8]) -> Option<(DynamicImage, Transform)> {
+    if media_type::detect_bytes(buffer) == Some(MediaType::Heif) {
+        // Already upright; `None` unless built with the `heif` feature
+        return heif::decode(buffer).map(|img| (img, Transform::IDENTITY));
+    }
+    if let Some(previews) = raw_preview::previews(buffer) {
+        let raw_orientation = previews
+            .orientation
+            .and_then(Orientation::from_exif)
+            .map(Transform::from_orientation);
+        let decoded = previews.jpegs.iter().find_map(|jpeg| decode_buffer(jpeg));
+        if let Some((img, orientation)) = decoded {
+            return Some((img, raw_orientation.unwrap_or(orientation)));
+        }
+    }
+    decode_buffer(buffer)
+}
+
+fn decode_buffer(buffer: &[u8]) -> Option<(DynamicImage, Transform)> {
+    let mut decoder = ImageReader::new(Cursor::new(buffer))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder
+        .orientation()
+        .map(Transform::from_orientation)
+        .unwrap_or_default();
+    let img = DynamicImage::from_decoder(decoder).ok()?;
+    Some((img, orientation))
+}
+
+fn orientation_of(transform: Transform) -> Orientation {
+    match (transform.rotation, transform.mirrored) {
+        (90, false) => Orientation::Rotate90,
+        (180, false) => Orientation::Rotate180,
+        (270, false) => Orientation::Rotate270,
+        (0, true) => Orientation::FlipHorizontal,
+        (180, true) => Orientation::FlipVertical,
+        (270, true) => Orientation::Rotate90FlipH,
+        (90, true) => Orientation::Rotate270FlipH,
+        _ => Orientation::NoTransforms,
+    }
+}
+
+/// Stores everything known about a file in the hash cache.
+fn remember(meta: &FileMetaData) {
+    HASH_CACHE.insert(
+        &meta.path,
+        CacheEntry {
+            stamp: meta.stamp,
+            byte_hash: meta.byte_hash.clone(),
+            perceptual: meta.perceptual,
+            orientation: meta.orientation,
+            upright: meta.upright,
+            dihedral: meta.dihedral.clone(),
+            thumbnail: meta.thumbnail.clone(),
+            decoded: meta.decoded,
+        },
+    );
+}
 
-    Ok(FileMetaData {
-        path,
-        size,
+/// Rebuilds the metadata from a cache entry. The preview is left out if the
+/// cached thumbnail has vanished or has another size, so the file gets decoded again.
+fn from_cache_entry(
+    path: &Path,
+    modified: SystemTime,
+    entry: CacheEntry,
+    preview: Option<u32>,
+) -> FileMetaData {
+    let preview_base64 = match (preview, &entry.byte_hash, &entry.thumbnail) {
+        (Some(size), Some(hash), Some(thumbnail))
+            if *thumbnail == HASH_CACHE.thumbnail_path(hash, size) =>
+        {
+            std::fs::read(thumbnail)
+                .ok()
+                .map(|webp| to_data_url(&webp).into())
+        }
+        _ => None,
+    };
+    FileMetaData {
+        path: path.to_path_buf(),
+        size: entry.stamp.size,
         modified,
-        byte_hash: Some(byte_hash),
-        perceptual_hash,
-        preview_base64, // Store the generated preview
-    })
+        stamp: entry.stamp,
+        byte_hash: entry.byte_hash,
+        perceptual: entry.perceptual,
+        orientation: entry.orientation,
+        upright: entry.upright,
+        dihedral: entry.dihedral,
+        preview_base64,
+        thumbnail: entry.thumbnail,
+        decoded: entry.decoded,
+        reference: false,
+    }
 }
 
 // --- Search Algorithms ---
 
-fn find_duplicates_by_byte_hash(metadata: &[FileMetaData]) -> Vec<MatchPair> {
-    let size_map = DashMap::<u64, Vec<FileMetaData>>::new();
-    for meta in metadata {
-        size_map.entry(meta.size).or_default().push(meta.clone());
+/// Finds exact duplicates in three stages, each reading more of fewer files:
+/// files of equal size, then equal partial hashes, then equal full hashes.
+/// Confirmed groups get their keeper suggested by `rules` and are passed to
+/// `on_group` right away. Computed hashes are written back into `metadata` so
+/// later methods and the cache can reuse them.
+fn find_duplicates_by_byte_hash(
+    metadata: &mut [FileMetaData],
+    options: DecodeOptions,
+    rules: &[KeeperRule],
+    cancel: &CancelToken,
+    on_group: &(dyn Fn(&MatchPair) + Sync),
+) -> Vec<MatchPair> {
+    let mut size_map = HashMap::<u64, Vec<usize>>::new();
+    for (i, meta) in metadata.iter().enumerate() {
+        size_map.entry(meta.size).or_default().push(i);
     }
 
-    size_map
-        .into_iter()
-        .par_bridge()
-        .filter(|(_, v)| v.len() > 1)
-        .flat_map(|(_, potential_duplicates)| {
-            let hash_map = DashMap::<String, Vec<FileMetaData>>::new();
-            for meta in potential_duplicates {
-                if let Some(hash) = &meta.byte_hash {
-                    hash_map.entry(hash.clone()).or_default().push(meta);
-                }
-            }
-            
-            hash_map
+    let shared: &[FileMetaData] = metadata;
+    let results: Vec<BucketResult> = size_map
+        .into_values()
+        // Copies among reference files alone are never reported, so they need no hashing
+        .filter(|bucket| bucket.len() > 1 && bucket.iter().any(|&i| !shared[i].reference))
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .filter(|_| !cancel.is_cancelled())
+        .map(|bucket| {
+            let confirmed = confirm_size_bucket(shared, bucket, options.preview);
+            let hashes: HashMap<usize, &String> =
+                confirmed.hashes.iter().map(|(i, hash)| (*i, hash)).collect();
+            let groups: Vec<(Vec<(usize, FileMetaData)>, MatchPair)> = confirmed
+                .groups
                 .into_iter()
-                .par_bridge()
-                .filter(|(_, v)| v.len() > 1)
-                .map(|(_, entries)| MatchPair {
-                    method: CompareMethod::ByteHash,
-                    files: entries.into_iter().map(to_file_info).collect(),
+                .filter(|indices| indices.iter().any(|&i| !shared[i].reference))
+                .map(|indices| {
+                    let mut members: Vec<(usize, FileMetaData)> = indices
+                        .into_iter()
+                        .map(|i| {
+                            let mut member = shared[i].clone();
+                            if member.byte_hash.is_none() {
+                                member.byte_hash = hashes.get(&i).map(|hash| (*hash).clone());
+                            }
+                            (i, member)
+                        })
+                        .collect();
+                    // Group members need a preview for the review screen
+                    if let Some(size) = options.preview {
+                        share_preview(&mut members, &confirmed.previews, size);
+                    }
+                    let files: Vec<FileInfo> =
+                        members.iter().map(|(_, m)| to_file_info(m.clone())).collect();
+                    let group = MatchPair {
+                        method: CompareMethod::ByteHash,
+                        distance: None,
+                        keeper: keeper::suggest(&files, rules),
+                        files,
+                    };
+                    on_group(&group);
+                    (members, group)
                 })
+                .collect();
+            BucketResult {
+                hashes: confirmed.hashes,
+                groups,
+            }
         })
-        .collect()
-}
-
-fn find_duplicates_by_perceptual_hash(metadata: &[FileMetaData], threshold: u32) -> Vec<MatchPair> {
-    let mut image_entries: Vec<FileMetaData> = metadata
-        .iter()
-        .filter(|meta| meta.perceptual_hash.is_some())
-        .cloned()
         .collect();
 
     let mut duplicate_groups = Vec::new();
+    for result in results {
+        for (i, hash) in result.hashes {
+            metadata[i].byte_hash = Some(hash);
+            remember(&metadata[i]);
+        }
+        for (members, group) in result.groups {
+            for (i, member) in members {
+                remember(&member);
+                metadata[i] = member;
+            }
+            duplicate_groups.push(group);
+        }
+    }
+    duplicate_groups
+}
 
-    while let Some(base_entry) = image_entries.pop() {
-        let base_phash = base_entry.perceptual_hash.unwrap();
-        let mut current_group = vec![base_entry];
+/// Outcome of one size bucket: full hashes computed for it and the confirmed
+/// groups, as indices into the metadata together with their updated entries,
+/// and as reported.
+struct BucketResult {
+    hashes: Vec<(usize, String)>,
+    groups: Vec<(Vec<(usize, FileMetaData)>, MatchPair)>,
+}
 
-        image_entries.retain(|other_entry| {
-            let other_phash = other_entry.perceptual_hash.unwrap();
-            if hamming_distance(base_phash, other_phash) <= threshold {
-                current_group.push(other_entry.clone());
-                false
-            } else {
-                true
+/// A preview together with the thumbnail it is stored as, if storing worked.
+#[derive(Clone)]
+struct Preview {
+    data_url: Arc<str>,
+    thumbnail: Option<PathBuf>,
+}
+
+/// What `confirm_size_bucket` found out about a bucket.
+struct ConfirmedBucket {
+    /// Full hashes computed for it
+    hashes: Vec<(usize, String)>,
+    /// Groups of identical files, as indices into the metadata
+    groups: Vec<Vec<usize>>,
+    /// Previews made from the bytes read for the full hashes, by hash
+    previews: HashMap<String, Preview>,
+}
+
+/// Narrows one bucket of equally sized files down to groups of identical files.
+/// With `preview` set, the first image read for each new hash also gets its
+/// preview made from the bytes already in memory.
+fn confirm_size_bucket(
+    metadata: &[FileMetaData],
+    bucket: Vec<usize>,
+    preview: Option<u32>,
+) -> ConfirmedBucket {
+    let size = metadata[bucket[0]].size;
+    let all_known = bucket.iter().all(|&i| metadata[i].byte_hash.is_some());
+
+    // Small files are read completely anyway, and known hashes need no I/O at all
+    let candidates: Vec<Vec<usize>> = if all_known || size <= 2 * byte_hash::PARTIAL_HASH_BYTES {
+        vec![bucket]
+    } else {
+        let mut partial_map = HashMap::<blake3::Hash, Vec<usize>>::new();
+        for i in bucket {
+            if let Ok(hash) = byte_hash::partial_hash(&metadata[i].path, size) {
+                partial_map.entry(hash).or_default().push(i);
             }
-        });
+        }
+        partial_map.into_values().filter(|v| v.len() > 1).collect()
+    };
 
-        if current_group.len() > 1 {
-            duplicate_groups.push(MatchPair {
-                method: CompareMethod::PerceptualDHash { threshold },
-                files: current_group.into_iter().map(to_file_info).collect(),
-            });
+    let mut confirmed = ConfirmedBucket {
+        hashes: Vec::new(),
+        groups: Vec::new(),
+        previews: HashMap::new(),
+    };
+    for candidate in candidates {
+        let mut hash_map = HashMap::<String, Vec<usize>>::new();
+        for i in candidate {
+            let hash = match &metadata[i].byte_hash {
+                Some(hash) => hash.clone(),
+                None => {
+                    let previews = &mut confirmed.previews;
+                    let hashed = byte_hash::full_hash_with(&metadata[i].path, |bytes, hash| {
+                        let Some(size) = preview else {
+                            return;
+                        };
+                        if previews.contains_key(hash)
+                            || media_type::detect_bytes(bytes).is_none_or(MediaType::is_video)
+                        {
+                            return;
+                        }
+                        let made = cached_preview(hash, size).or_else(|| {
+                            let (img, orientation) = decode_image(bytes)?;
+                            make_preview(img, orientation, hash, size)
+                        });
+                        if let Some(made) = made {
+                            previews.insert(hash.to_string(), made);
+                        }
+                    });
+                    match hashed {
+                        Ok(hash) => {
+                            confirmed.hashes.push((i, hash.clone()));
+                            hash
+                        }
+                        Err(_) => continue,
+                    }
+                }
+            };
+            hash_map.entry(hash).or_default().push(i);
         }
+        confirmed
+            .groups
+            .extend(hash_map.into_values().filter(|v| v.len() > 1));
     }
+    confirmed
+}
 
-    duplicate_groups
+/// Gives all files of a byte hash group the same preview, since identical
+/// files look identical: one a member already has, one made while hashing or
+/// a cached thumbnail. Only without any of these is a member read again.
+fn share_preview(
+    members: &mut [(usize, FileMetaData)],
+    made: &HashMap<String, Preview>,
+    size: u32,
+) {
+    let hash = members[0].1.byte_hash.clone().unwrap_or_default();
+    let existing = members.iter().find_map(|(_, member)| {
+        Some(Preview {
+            data_url: member.preview_base64.clone()?,
+            thumbnail: member.thumbnail.clone(),
+        })
+    });
+    let preview = existing
+        .or_else(|| made.get(&hash).cloned())
+        .or_else(|| cached_preview(&hash, size))
+        .or_else(|| {
+            let first = &mut members[0].1;
+            let options = DecodeOptions {
+                decode: true,
+                dihedral: false,
+                hashes: HashSelection::default(),
+                preview: Some(size),
+                full_hash: false,
+            };
+            decode_file(first, options).ok()?;
+            Some(Preview {
+                data_url: first.preview_base64.clone()?,
+                thumbnail: first.thumbnail.clone(),
+            })
+        });
+    let Some(preview) = preview else {
+        return;
+    };
+    for (_, member) in members.iter_mut() {
+        if member.preview_base64.is_none() {
+            member.preview_base64 = Some(preview.data_url.clone());
+            member.thumbnail = preview.thumbnail.clone();
+        }
+    }
+}
+
+/// The stored thumbnail of a content hash, if an earlier scan made one.
+fn cached_preview(byte_hash: &str, size: u32) -> Option<Preview> {
+    let thumbnail = HASH_CACHE.thumbnail_path(byte_hash, size);
+    let webp = std::fs::read(&thumbnail).ok()?;
+    Some(Preview {
+        data_url: to_data_url(&webp).into(),
+        thumbnail: Some(thumbnail),
+    })
+}
+
+/// Groups images whose fingerprints are connected within the method's
+/// threshold. Entries are sorted by path first, so the same files always
+/// produce the same groups.
+fn find_duplicates_by_perceptual_hash(
+    metadata: &[FileMetaData],
+    method: &CompareMethod,
+    cancel: &CancelToken,
+) -> Vec<MatchPair> {
+    let Some(params) = method.perceptual() else {
+        return Vec::new();
+    };
+    let orientation = params.orientation;
+    let hash_of = |hashes: &PerceptualHashes| hashes.get(params.algorithm, params.hash_size);
+    let variants = match orientation {
+        OrientationMode::Dihedral => 8,
+        _ => 1,
+    };
+
+    // Files without every hash the method compares, like non-images, are left out
+    let variants_of = |meta: &FileMetaData| -> Option<Vec<ImageHash>> {
+        match orientation {
+            OrientationMode::AsStored => Some(vec![hash_of(&meta.perceptual)?]),
+            OrientationMode::Exif => Some(vec![hash_of(meta.upright.as_ref().unwrap_or(&meta.perceptual))?]),
+            OrientationMode::Dihedral => meta.dihedral.as_ref()?.iter().map(hash_of).collect(),
+        }
+        .filter(|hashes| hashes.len() == variants)
+    };
+    let mut image_entries: Vec<(&FileMetaData, Vec<ImageHash>)> = metadata
+        .iter()
+        .filter_map(|meta| Some((meta, variants_of(meta)?)))
+        .collect();
+    image_entries.sort_by(|a, b| a.0.path.cmp(&b.0.path));
+
+    let hashes: Vec<ImageHash> = image_entries
+        .iter()
+        .flat_map(|(_, hashes)| hashes.iter().copied())
+        .collect();
+
+    perceptual_groups::connected_components(&hashes, variants, params.threshold, cancel)
+        .into_iter()
+        .filter(|component| component.members.iter().any(|&i| !image_entries[i].0.reference))
+        .map(|component| {
+            let first_orientation = image_entries[component.members[0]].0.orientation;
+            let files = component
+                .members
+                .iter()
+                .zip(&component.transforms)
+                .map(|(&i, &transform)| {
+                    let entry = image_entries[i].0;
+                    let transform = match orientation {
+                        OrientationMode::AsStored => None,
+                        // Both files were turned upright, so undo the first one's turn
+                        OrientationMode::Exif => {
+                            Some(first_orientation.inverse().after(entry.orientation))
+                        }
+                        OrientationMode::Dihedral => Some(transform),
+                    };
+                    FileInfo {
+                        transform,
+                        ..to_file_info(entry.clone())
+                    }
+                })
+                .collect();
+            MatchPair {
+                method: method.clone(),
+                distance: Some(component.distance),
+                files,
+                keeper: None,
+            }
+        })
+        .collect()
 }
 
 // --- Helper Functions ---
 
-/// Generates a JPEG thumbnail with specific quality,
-/// encodes it as Base64, and returns it as a data URL string.
-fn generate_preview_base64(img: &DynamicImage) -> Result<String, ImageError> {
-    let thumbnail = img.thumbnail(200, 200);
+/// Makes the preview for the review screen, upright like any image viewer
+/// would show it, and stores it as thumbnail of the content hash.
+fn make_preview(
+    mut img: DynamicImage,
+    orientation: Transform,
+    byte_hash: &str,
+    size: u32,
+) -> Option<Preview> {
+    img.apply_orientation(orientation_of(orientation));
+    let webp = generate_preview_webp(&img, size).ok()?;
+    Some(Preview {
+        thumbnail: HASH_CACHE.store_thumbnail(byte_hash, size, &webp),
+        data_url: to_data_url(&webp).into(),
+    })
+}
+
+/// Generates a WebP thumbnail of at most `size`x`size` pixels.
+fn generate_preview_webp(img: &DynamicImage, size: u32) -> Result<Vec<u8>, ImageError> {
+    let thumbnail = img.thumbnail(size, size);
     let mut buffer = Cursor::new(Vec::new());
 
     // Write the thumbnail directly to the buffer in WebP format for better performance.
     thumbnail.write_to(&mut buffer, ImageFormat::WebP)?;
-
-    let base64_string = general_purpose::STANDARD.encode(buffer.get_ref());
-    Ok(format!("data:image/webp;base64,{}", base64_string))
-}
-
-/// Computes the dHash of a pre-decoded image.
-fn compute_dhash(img: &DynamicImage) -> Result<u64, String> {
-    let luma_img = img.to_luma8();
-    let width = 9;
-    let height = 8;
-    let resized = image::imageops::resize(&luma_img, width, height, FilterType::Triangle);
-    let pixels = resized.into_raw();
-
-    let mut bits = 0u64;
-    for y in 0..height {
-        for x in 0..(width - 1) {
-            let idx = (y * width + x) as usize;
-            if pixels[idx] > pixels[idx + 1] {
-                let bit_pos = y * (width - 1) + x;
-                bits |= 1 << bit_pos;
-            }
-        }
-    }
-    Ok(bits)
+    Ok(buffer.into_inner())
 }
 
-fn hamming_distance(a: u64, b: u64) -> u32 {
-    (a ^ b).count_ones()
+/// Encodes a WebP thumbnail as Base64 and returns it as a data URL string.
+fn to_data_url(webp: &[u8]) -> String {
+    let base64_string = general_purpose::STANDARD.encode(webp);
+    format!("data:image/webp;base64,{}", base64_string)
 }
 
 /// Converts the internal `FileMetaData` to the `FileInfo` struct for the frontend.
@@ -287,30 +896,51 @@ fn to_file_info(entry: FileMetaData) -> FileInfo {
         .map(|d| d.as_secs())
         .unwrap_or_default();
         
-    let dhash_str = entry.perceptual_hash.map(|h| format!("{:016x}", h));
+    let hex = |hash: Option<u64>| hash.map(|h| format!("{:016x}", h));
 
     FileInfo {
         hash: entry.byte_hash,
-        dhash: dhash_str,
+        dhash: hex(entry.perceptual.dhash),
+        ahash: hex(entry.perceptual.ahash),
+        phash: hex(entry.perceptual.phash),
+        whash: hex(entry.perceptual.whash),
+        transform: None,
+        reference: entry.reference,
         size: entry.size,
         path: entry.path.display().to_string(),
         age,
         // CORRECTED: Pass the generated preview to the frontend
-        preview: entry.preview_base64,
+        preview: entry.preview_base64.map(|p| p.to_string()),
     }
 }
 
+// Only to the calling window, so other windows never see groups of a scan they did not start
+fn emit_group(window: &Window, scan_id: &str, key: String, group: MatchPair) {
+    let _ = window.emit_to(
+        window.label(),
+        "duplicate_group",
+        DuplicateGroupEvent {
+            scan_id: scan_id.to_string(),
+            key,
+            group,
+        },
+    );
+}
+
 fn emit_progress(
     window: &Window,
+    scan_id: &str,
     start: Instant,
     processed: usize,
     total: usize,
     current: String,
 ) {
     let elapsed = start.elapsed().as_secs_f32();
-    let _ = window.emit(
+    let _ = window.emit_to(
+        window.label(),
         "duplicate_progress",
         DuplicateProgress {
+            scan_id: scan_id.to_string(),
             processed,
             total,
             elapsed,
@@ -318,3 +948,254 @@ fn emit_progress(
         },
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duplicate::{FileFilter, PreviewSettings};
+    use image::GrayImage;
+
+    fn rebuild_config(root: &Path) -> ScanConfig {
+        ScanConfig {
+            roots: vec![ScanRoot {
+                path: root.to_path_buf(),
+                reference: false,
+            }],
+            methods: Vec::new(),
+            stream_groups: false,
+            filter: FileFilter::default(),
+            preview: PreviewSettings {
+                enabled: false,
+                size: 200,
+            },
+            keeper_rules: Vec::new(),
+            rebuild: true,
+        }
+    }
+
+    #[test]
+    fn rebuild_hashes_every_file_into_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let photo = dir.path().join("photo.jpg");
+        image::RgbImage::from_pixel(16, 16, image::Rgb([200, 40, 40]))
+            .save(&photo)
+            .unwrap();
+        let clip = dir.path().join("clip.mp4");
+        let mut movie = b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2".to_vec();
+        movie.extend([7u8; 4096]);
+        std::fs::write(&clip, movie).unwrap();
+
+        let options = DecodeOptions::for_scan(&rebuild_config(dir.path()));
+        assert!(options.decode && options.full_hash);
+        let cache_hits = AtomicUsize::new(0);
+        for path in [&photo, &clip] {
+            process_file_once(path.clone(), options, &cache_hits).unwrap();
+            let stamp = FileStamp::from_metadata(&std::fs::metadata(path).unwrap());
+            let entry = HASH_CACHE.get(path, stamp).expect("no cache entry");
+            assert_eq!(entry.byte_hash, Some(byte_hash::full_hash(path).unwrap()));
+        }
+        let photo_stamp = FileStamp::from_metadata(&std::fs::metadata(&photo).unwrap());
+        let photo_entry = HASH_CACHE.get(&photo, photo_stamp).unwrap();
+        assert!(photo_entry.decoded && photo_entry.perceptual.selection() == HashSelection::ALL);
+    }
+
+    #[test]
+    fn byte_hash_scans_do_not_decode() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ScanConfig {
+            methods: vec![CompareMethod::ByteHash],
+            rebuild: false,
+            ..rebuild_config(dir.path())
+        };
+        let options = DecodeOptions::for_scan(&config);
+        assert!(!options.decode && !options.full_hash);
+    }
+
+    #[test]
+    fn only_files_of_unknown_type_are_sniffed() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = b"\xff\xd8\xff\xe0 photo";
+        std::fs::write(dir.path().join("photo"), jpeg).unwrap();
+        std::fs::write(dir.path().join("photo.bin"), jpeg).unwrap();
+        std::fs::write(dir.path().join("photo.xmp"), jpeg).unwrap();
+        std::fs::write(dir.path().join("photo.txt"), jpeg).unwrap();
+        std::fs::write(dir.path().join("notes.dat"), "no photo").unwrap();
+        // Taken by its name; its content is checked when it is decoded
+        std::fs::write(dir.path().join("broken.jpg"), "no photo either").unwrap();
+
+        let config = rebuild_config(dir.path());
+        let filter = config.filter.compile().unwrap();
+        let mut found: Vec<_> = find_allowed_files(&config.roots, &filter, &CancelToken::default())
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        found.sort();
+        assert_eq!(found, ["broken.jpg", "photo", "photo.bin"]);
+    }
+
+    #[test]
+    fn media_extensions_do_not_get_other_files_decoded() {
+        let dir = tempfile::tempdir().unwrap();
+        let page = dir.path().join("page.jpg");
+        std::fs::write(&page, "<!DOCTYPE html><html></html>").unwrap();
+        let clip = dir.path().join("clip.jpg");
+        let mut movie = b"\0\0\0\x18ftypisom\0\0\x02\0isomiso2".to_vec();
+        movie.extend([7u8; 4096]);
+        std::fs::write(&clip, movie).unwrap();
+
+        let config = ScanConfig {
+            methods: vec![CompareMethod::PerceptualDHash {
+                threshold: 10,
+                orientation: OrientationMode::AsStored,
+                hash_size: Default::default(),
+            }],
+            rebuild: false,
+            ..rebuild_config(dir.path())
+        };
+        let options = DecodeOptions::for_scan(&config);
+        let cache_hits = AtomicUsize::new(0);
+        for path in [page, clip] {
+            let meta = process_file_once(path, options, &cache_hits).unwrap();
+            assert!(meta.perceptual.dhash.is_none());
+            // Neither was read as a whole
+            assert!(meta.byte_hash.is_none());
+        }
+    }
+
+    /// A file as a dihedral scan would have decoded it.
+    fn decoded(path: &str, img: &GrayImage, hashes: HashSelection) -> FileMetaData {
+        let img = DynamicImage::ImageLuma8(img.clone());
+        let fingerprints = perceptual_hash::fingerprint(&img, Transform::IDENTITY, true, hashes);
+        FileMetaData {
+            path: PathBuf::from(path),
+            size: 0,
+            modified: SystemTime::UNIX_EPOCH,
+            stamp: FileStamp {
+                size: 0,
+                modified_ns: 0,
+                inode: 0,
+            },
+            byte_hash: None,
+            perceptual: fingerprints.stored,
+            orientation: Transform::IDENTITY,
+            upright: fingerprints.upright,
+            dihedral: fingerprints.dihedral,
+            preview_base64: None,
+            thumbnail: None,
+            decoded: true,
+            reference: false,
+        }
+    }
+
+    /// A bright block in one corner of a gradient, so every rotation and
+    /// mirror image looks different.
+    fn photo() -> GrayImage {
+        GrayImage::from_fn(96, 64, |x, y| {
+            let block = x < 32 && y < 24;
+            image::Luma([if block { 255 } else { (x + y) as u8 }])
+        })
+    }
+
+    fn dihedral_dhash() -> CompareMethod {
+        CompareMethod::PerceptualDHash {
+            threshold: 4,
+            orientation: OrientationMode::Dihedral,
+            hash_size: HashSize::Small,
+        }
+    }
+
+    #[test]
+    fn rotated_and_mirrored_copies_join_their_original() {
+        let hashes = HashSelection::default().with(PerceptualAlgorithm::Difference, HashSize::Small);
+        let metadata = [
+            decoded("c.png", &image::imageops::flip_horizontal(&photo()), hashes),
+            decoded("a.png", &photo(), hashes),
+            decoded("b.png", &image::imageops::rotate90(&photo()), hashes),
+        ];
+        let groups =
+            find_duplicates_by_perceptual_hash(&metadata, &dihedral_dhash(), &CancelToken::default());
+
+        assert_eq!(groups.len(), 1);
+        let files: Vec<_> = groups[0]
+            .files
+            .iter()
+            .map(|file| (file.path.as_str(), file.transform))
+            .collect();
+        let turn = |rotation, mirrored| Some(Transform { rotation, mirrored });
+        assert_eq!(
+            files,
+            [
+                ("a.png", turn(0, false)),
+                // Turned back by another 270 degrees
+                ("b.png", turn(270, false)),
+                ("c.png", turn(0, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn files_without_the_compared_hashes_are_left_out() {
+        let hashes = HashSelection::default().with(PerceptualAlgorithm::Difference, HashSize::Small);
+        let mut unrotated = decoded("b.png", &photo(), hashes);
+        unrotated.dihedral = None;
+        // Other fingerprints in all orientations; alike, if missing hashes counted as zero
+        let other = HashSelection::default().with(PerceptualAlgorithm::Average, HashSize::Small);
+        let [mut c, mut d] = ["c.png", "d.png"].map(|path| decoded(path, &photo(), other));
+        c.perceptual = unrotated.perceptual;
+        d.perceptual = unrotated.perceptual;
+        let metadata = [decoded("a.png", &photo(), hashes), unrotated, c, d];
+        let groups =
+            find_duplicates_by_perceptual_hash(&metadata, &dihedral_dhash(), &CancelToken::default());
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn byte_hash_groups_are_reported_with_their_keeper() {
+        let dir = tempfile::tempdir().unwrap();
+        let keep = dir.path().join("keep");
+        std::fs::create_dir(&keep).unwrap();
+        let paths = [
+            dir.path().join("copy.jpg"),
+            keep.join("photo.jpg"),
+            dir.path().join("other.jpg"),
+        ];
+        std::fs::write(&paths[0], "same photo").unwrap();
+        std::fs::write(&paths[1], "same photo").unwrap();
+        // Same size, so it is hashed as well
+        std::fs::write(&paths[2], "else photo").unwrap();
+
+        let config = ScanConfig {
+            methods: vec![CompareMethod::ByteHash],
+            rebuild: false,
+            ..rebuild_config(dir.path())
+        };
+        let options = DecodeOptions::for_scan(&config);
+        let cache_hits = AtomicUsize::new(0);
+        let mut metadata: Vec<FileMetaData> = paths
+            .iter()
+            .map(|path| process_file_once(path.clone(), options, &cache_hits).unwrap())
+            .collect();
+        let rules = [KeeperRule::PathPrefix {
+            prefix: keep.clone(),
+        }];
+        let streamed = std::sync::Mutex::new(Vec::new());
+        let groups = find_duplicates_by_byte_hash(
+            &mut metadata,
+            options,
+            &rules,
+            &CancelToken::default(),
+            &|group| streamed.lock().unwrap().push(group.clone()),
+        );
+
+        assert_eq!(groups.len(), 1);
+        let keeper = groups[0].keeper.as_ref().unwrap();
+        assert_eq!(keeper.path, paths[1].display().to_string());
+        assert_eq!(keeper.rule, Some(rules[0].clone()));
+        let streamed = streamed.into_inner().unwrap();
+        assert_eq!(streamed.len(), 1);
+        assert_eq!(streamed[0].keeper.as_ref().unwrap().path, keeper.path);
+        for (meta, path) in metadata.iter().zip(&paths) {
+            assert_eq!(meta.byte_hash, Some(byte_hash::full_hash(path).unwrap()));
+        }
+    }
+}
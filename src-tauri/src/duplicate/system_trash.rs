@@ -0,0 +1,64 @@
+//! The trash of macOS and Windows, reached through the `trash` crate. Unlike
+//! the freedesktop.org trash, the system keeps track of where a file went.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+pub fn trash_file(path: &Path) -> io::Result<()> {
+    trash::delete(path).map_err(io_error)
+}
+
+/// Puts the most recently trashed file that used to live at `original` back.
+#[cfg(target_os = "windows")]
+pub fn restore_file(original: &Path) -> io::Result<()> {
+    use trash::os_limited;
+
+    let original = std::path::absolute(original)?;
+    if fs::symlink_metadata(&original).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("'{}' already exists", original.display()),
+        ));
+    }
+    let latest = os_limited::list()
+        .map_err(io_error)?
+        .into_iter()
+        .filter(|item| item.original_path() == original)
+        .max_by_key(|item| item.time_deleted);
+    let Some(item) = latest else {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("'{}' is not in the trash", original.display()),
+        ));
+    };
+    os_limited::restore_all([item]).map_err(io_error)
+}
+
+/// macOS offers no way to put a file back for other programs than the Finder.
+#[cfg(target_os = "macos")]
+pub fn restore_file(original: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(original).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("'{}' already exists", original.display()),
+        ));
+    }
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "put the file back with \"Put Back\" in the trash of the Finder",
+    ))
+}
+
+fn io_error(error: trash::Error) -> io::Error {
+    match error {
+        trash::Error::CouldNotAccess { target } => {
+            io::Error::new(ErrorKind::NotFound, format!("cannot access '{}'", target))
+        }
+        trash::Error::RestoreCollision { path, .. } => io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("'{}' already exists", path.display()),
+        ),
+        error => io::Error::other(error.to_string()),
+    }
+}
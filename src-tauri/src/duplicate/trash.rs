@@ -0,0 +1,322 @@
+//! Moving files to the trash as described by the freedesktop.org Trash
+//! specification, and putting them back.
+//!
+//! Files on the home volume go to `$XDG_DATA_HOME/Trash`. Files on other
+//! volumes go to `$topdir/.Trash/$uid` if the administrator set that up, or to
+//! `$topdir/.Trash-$uid` otherwise, so trashing never copies data across drives.
+
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// A file that was moved to the trash.
+#[derive(Debug, Clone, Serialize)]
+pub struct TrashedFile {
+    pub original: PathBuf,
+    /// Where the file now lives inside the trash
+    pub trashed: PathBuf,
+    /// The `.trashinfo` file describing it
+    pub info: PathBuf,
+}
+
+/// One trash directory with its `files` and `info` subdirectories.
+struct TrashDir {
+    root: PathBuf,
+    /// Set for per-volume trashes, whose `.trashinfo` paths are relative to it
+    topdir: Option<PathBuf>,
+}
+
+impl TrashDir {
+    fn files(&self) -> PathBuf {
+        self.root.join("files")
+    }
+
+    fn info(&self) -> PathBuf {
+        self.root.join("info")
+    }
+
+    fn create(&self) -> io::Result<()> {
+        for dir in [self.files(), self.info()] {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("cannot create trash folder '{}': {}", dir.display(), e),
+                    )
+                })?;
+        }
+        Ok(())
+    }
+
+    /// The path as it is written into a `.trashinfo` file.
+    fn stored_path(&self, original: &Path) -> PathBuf {
+        match &self.topdir {
+            Some(topdir) => original
+                .strip_prefix(topdir)
+                .unwrap_or(original)
+                .to_path_buf(),
+            None => original.to_path_buf(),
+        }
+    }
+
+    fn original_path(&self, stored: PathBuf) -> PathBuf {
+        match &self.topdir {
+            Some(topdir) if stored.is_relative() => topdir.join(stored),
+            _ => stored,
+        }
+    }
+}
+
+fn uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+fn home_trash() -> io::Result<TrashDir> {
+    let data_dir = dirs::data_dir().ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, "cannot determine the home data folder")
+    })?;
+    Ok(TrashDir {
+        root: data_dir.join("Trash"),
+        topdir: None,
+    })
+}
+
+/// The mount point of the volume holding `path`.
+fn topdir(path: &Path) -> io::Result<PathBuf> {
+    let dev = fs::symlink_metadata(path)?.dev();
+    let mut topdir = path.to_path_buf();
+    while let Some(parent) = topdir.parent() {
+        match fs::metadata(parent) {
+            Ok(meta) if meta.dev() == dev => topdir = parent.to_path_buf(),
+            _ => break,
+        }
+    }
+    Ok(topdir)
+}
+
+/// The per-volume trash of `topdir`: the shared `.Trash/$uid` if `.Trash` is a
+/// real sticky directory, else `.Trash-$uid`.
+fn volume_trash(topdir: &Path) -> TrashDir {
+    let shared = topdir.join(".Trash");
+    let usable = fs::symlink_metadata(&shared)
+        .is_ok_and(|meta| meta.is_dir() && meta.permissions().mode() & 0o1000 != 0);
+    let root = if usable {
+        shared.join(uid().to_string())
+    } else {
+        topdir.join(format!(".Trash-{}", uid()))
+    };
+    TrashDir {
+        root,
+        topdir: Some(topdir.to_path_buf()),
+    }
+}
+
+/// The trash a file on the volume of `path` belongs in.
+fn trash_for(path: &Path) -> io::Result<TrashDir> {
+    let home = home_trash()?;
+    home.create()?;
+    let home_dev = fs::metadata(&home.root)?.dev();
+    let dev = fs::symlink_metadata(path)?.dev();
+    if dev == home_dev {
+        return Ok(home);
+    }
+    let trash = volume_trash(&topdir(path)?);
+    trash.create()?;
+    Ok(trash)
+}
+
+/// Moves a file to the trash. The `.trashinfo` file is created first and
+/// exclusively, which reserves the name even against other trashing programs.
+pub fn trash_file(path: &Path) -> io::Result<TrashedFile> {
+    let original = std::path::absolute(path)?;
+    let trash = trash_for(&original)?;
+    let name = original.file_name().ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("'{}' has no file name", original.display()),
+        )
+    })?;
+    let stem = Path::new(name)
+        .file_stem()
+        .unwrap_or(name)
+        .to_string_lossy();
+    let extension = Path::new(name).extension().map(|e| e.to_string_lossy());
+
+    for n in 0.. {
+        let candidate = match (n, &extension) {
+            (0, _) => name.to_string_lossy().into_owned(),
+            (_, Some(ext)) => format!("{}.{}.{}", stem, n, ext),
+            (_, None) => format!("{}.{}", stem, n),
+        };
+        let info = trash.info().join(format!("{}.trashinfo", candidate));
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        };
+        let contents = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(&trash.stored_path(&original)),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        );
+        let trashed = trash.files().join(&candidate);
+        let moved = file
+            .write_all(contents.as_bytes())
+            .and_then(|_| fs::rename(&original, &trashed));
+        if let Err(e) = moved {
+            let _ = fs::remove_file(&info);
+            return Err(e);
+        }
+        return Ok(TrashedFile {
+            original,
+            trashed,
+            info,
+        });
+    }
+    unreachable!("the name search only ends by returning")
+}
+
+/// Puts the most recently trashed file that used to live at `original` back.
+pub fn restore_file(original: &Path) -> io::Result<PathBuf> {
+    let original = std::path::absolute(original)?;
+    if fs::symlink_metadata(&original).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("'{}' already exists", original.display()),
+        ));
+    }
+    let mut trashes = vec![home_trash()?];
+    // The volume trash is found through the closest folder that still exists
+    if let Some(existing) = original.ancestors().find(|p| p.exists()) {
+        trashes.push(volume_trash(&topdir(existing)?));
+    }
+
+    let latest = trashes
+        .iter()
+        .flat_map(|trash| entries(trash).into_iter().map(move |entry| (trash, entry)))
+        .filter(|(trash, entry)| trash.original_path(entry.path.clone()) == original)
+        .max_by_key(|(_, entry)| (entry.deletion_date.clone(), entry.written));
+    let Some((trash, entry)) = latest else {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("'{}' is not in the trash", original.display()),
+        ));
+    };
+
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(trash.files().join(&entry.name), &original)?;
+    let _ = fs::remove_file(trash.info().join(format!("{}.trashinfo", entry.name)));
+    Ok(original)
+}
+
+struct TrashInfo {
+    /// Name of the entry in `files/`
+    name: String,
+    path: PathBuf,
+    deletion_date: String,
+    /// Orders entries trashed within the same second, which `deletion_date` cannot
+    written: Option<std::time::SystemTime>,
+}
+
+/// Reads all `.trashinfo` files of a trash; unreadable ones are skipped.
+fn entries(trash: &TrashDir) -> Vec<TrashInfo> {
+    let Ok(dir) = fs::read_dir(trash.info()) else {
+        return Vec::new();
+    };
+    dir.filter_map(Result::ok)
+        .filter_map(|entry| {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let name = file_name.strip_suffix(".trashinfo")?.to_string();
+            let contents = fs::read_to_string(entry.path()).ok()?;
+            let value = |key: &str| {
+                contents
+                    .lines()
+                    .find_map(|line| line.strip_prefix(key)?.strip_prefix('='))
+                    .map(str::to_string)
+            };
+            Some(TrashInfo {
+                name,
+                path: decode_path(&value("Path")?)?,
+                deletion_date: value("DeletionDate").unwrap_or_default(),
+                written: entry.metadata().and_then(|m| m.modified()).ok(),
+            })
+        })
+        .collect()
+}
+
+/// Percent-encodes a path the way the specification asks for URLs.
+fn encode_path(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn decode_path(encoded: &str) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(PathBuf::from(std::ffi::OsString::from_vec(decoded)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    #[test]
+    fn encodes_everything_but_unreserved_characters() {
+        assert_eq!(
+            encode_path(Path::new("/home/anna/Fotos 2024/Café #1 (50%).jpg")),
+            "/home/anna/Fotos%202024/Caf%C3%A9%20%231%20%2850%25%29.jpg"
+        );
+        assert_eq!(encode_path(Path::new("/a-b_c.d~e")), "/a-b_c.d~e");
+    }
+
+    #[test]
+    fn decoding_reverses_encoding() {
+        for path in [
+            Path::new("/home/anna/Fotos 2024/Café #1 (50%).jpg"),
+            Path::new("/tmp/100%/a+b=c&d"),
+            // Not valid UTF-8, which Linux file names need not be
+            Path::new(OsStr::from_bytes(b"/tmp/\xff\xfe.jpg")),
+        ] {
+            assert_eq!(decode_path(&encode_path(path)).as_deref(), Some(path));
+        }
+        // Lower case hex digits are valid as well
+        assert_eq!(decode_path("/a%c3%a9"), Some(PathBuf::from("/aé")));
+    }
+
+    #[test]
+    fn rejects_broken_escapes() {
+        assert_eq!(decode_path("/a%2"), None);
+        assert_eq!(decode_path("/a%zz.jpg"), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Just enough of a TIFF/EXIF reader to look at the tags of a photo.
+
+use image::{ImageDecoder, ImageReader};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// RAW files keep their EXIF IFDs close to the start of the file.
+const RAW_HEADER_BYTES: u64 = 1024 * 1024;
+
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+
+pub const MODEL: u16 = 0x0110;
+pub const LENS_MODEL: u16 = 0xa434;
+pub const DATE_TIME_ORIGINAL: u16 = 0x9003;
+/// DateTimeDigitized in the specification, CreateDate in most tools
+pub const CREATE_DATE: u16 = 0x9004;
+pub const OFFSET_TIME: u16 = 0x9010;
+pub const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+pub const OFFSET_TIME_DIGITIZED: u16 = 0x9012;
+
+/// The TIFF field type of text values.
+const ASCII: u16 = 2;
+
+#[derive(Debug, Clone, Default)]
+pub struct ExifData {
+    /// Tags found in the primary, Exif and GPS directories
+    pub tags: Vec<u16>,
+    /// Values of the text tags among them
+    pub strings: HashMap<u16, String>,
+}
+
+impl ExifData {
+    /// The value of a text tag, unless it is empty.
+    pub fn string(&self, tag: u16) -> Option<&str> {
+        self.strings
+            .get(&tag)
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+    }
+}
+
+/// Reads the EXIF tags of an image file. TIFF based RAW files are parsed
+/// directly, everything else goes through the decoder of the `image` crate.
+pub fn read(path: &Path) -> Option<ExifData> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(RAW_HEADER_BYTES)
+        .read_to_end(&mut header)
+        .ok()?;
+    if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        return parse(&header);
+    }
+    let mut decoder = ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    parse(&decoder.exif_metadata().ok()??)
+}
+
+/// Parses a TIFF structure as found in EXIF chunks, following the Exif and GPS
+/// sub-directories of the primary image.
+pub fn parse(tiff: &[u8]) -> Option<ExifData> {
+    let reader = Reader::new(tiff)?;
+    let primary = reader.u32(4)? as usize;
+    let mut exif = ExifData::default();
+    for at in reader.entries(primary) {
+        let Some(tag) = reader.u16(at) else { continue };
+        if tag == EXIF_IFD_POINTER || tag == GPS_IFD_POINTER {
+            // The offset of a sub-directory is stored as a single LONG value
+            if let Some(offset) = reader.u32(at + 8) {
+                for at in reader.entries(offset as usize) {
+                    reader.read_entry(at, &mut exif);
+                }
+            }
+        } else {
+            reader.read_entry(at, &mut exif);
+        }
+    }
+    Some(exif)
+}
+
+/// Reads the values of a TIFF structure in its byte order.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(tiff: &'a [u8]) -> Option<Self> {
+        Some(Reader {
+            data: tiff,
+            big_endian: match tiff.get(..2)? {
+                b"II" => false,
+                b"MM" => true,
+                _ => return None,
+            },
+        })
+    }
+
+    pub fn u16(&self, at: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub fn u32(&self, at: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_entry(&self, at: usize, exif: &mut ExifData) {
+        let Some(tag) = self.u16(at) else { return };
+        exif.tags.push(tag);
+        if self.u16(at + 2) == Some(ASCII) {
+            if let Some(text) = self.ascii(at) {
+                exif.strings.insert(tag, text);
+            }
+        }
+    }
+
+    /// The text of an ASCII entry; values of up to four bytes are stored in
+    /// the entry itself, longer ones at an offset.
+    fn ascii(&self, at: usize) -> Option<String> {
+        let count = self.u32(at + 4)? as usize;
+        let start = if count <= 4 {
+            at + 8
+        } else {
+            self.u32(at + 8)? as usize
+        };
+        let bytes = self.data.get(start..start.checked_add(count)?)?;
+        let text = bytes.split(|&b| b == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(text).into_owned())
+    }
+
+    /// Offsets of the 12-byte entries of the IFD at `offset`.
+    pub fn entries(&self, offset: usize) -> impl Iterator<Item = usize> {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        (0..count).map(move |i| offset + 2 + 12 * i)
+    }
+}
@@ -4,5 +4,65 @@ pub const ALLOWED_EXTENSIONS: &[&str] = &[
     // Modern / mobile formats
     "heic", "heif",
     // RAW formats of various camera vendors
-    "raw", "arw", "dng", "cr2", "nef", "pef", "rw2", "sr2",
+    "raw", "arw", "dng", "cr2", "nef", "pef", "rw2", "sr2", "cr3", "raf",
 ];
+
+/// Camera RAW formats, a subset of `ALLOWED_EXTENSIONS`.
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "raw", "arw", "dng", "cr2", "nef", "pef", "rw2", "sr2", "cr3", "raf",
+];
+
+/// Video clips from phones and cameras. Only their bytes are compared by
+/// duplicate scans, they are never decoded.
+pub const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "m4v", "mts", "m2ts", "3gp", "avi"];
+
+/// Files written next to a photo by editors and cameras: XMP metadata, Apple
+/// Photos edits, camera thumbnails and RawTherapee profiles.
+pub const SIDECAR_EXTENSIONS: &[&str] = &["xmp", "aae", "thm", "pp3"];
+
+/// Files that are common next to photos but are neither photos nor videos:
+/// documents, web pages, archives and audio. Duplicate scans pick up media
+/// files by their content only if their extension is none of these.
+pub const OTHER_EXTENSIONS: &[&str] = &[
+    "txt", "md", "pdf", "doc", "docx", "odt", "rtf", "xls", "xlsx", "ods", "ppt", "pptx", "csv",
+    "htm", "html", "xml", "json", "js", "css", "ini", "log", "db", "zip", "rar", "7z", "gz", "tar",
+    "mp3", "wav", "flac", "aac", "m4a", "ogg", "exe", "dll", "lnk", "url",
+];
+
+pub fn is_raw(path: &std::path::Path) -> bool {
+    has_extension(path, RAW_EXTENSIONS)
+}
+
+pub fn is_sidecar(path: &std::path::Path) -> bool {
+    has_extension(path, SIDECAR_EXTENSIONS)
+}
+
+pub fn is_allowed(path: &std::path::Path) -> bool {
+    has_extension(path, ALLOWED_EXTENSIONS)
+}
+
+pub fn is_video(path: &std::path::Path) -> bool {
+    has_extension(path, VIDEO_EXTENSIONS)
+}
+
+/// Whether the extension is known to belong to something else than a photo
+/// or a video, like a sidecar or a document.
+pub fn is_other(path: &std::path::Path) -> bool {
+    is_sidecar(path) || has_extension(path, OTHER_EXTENSIONS)
+}
+
+/// Whether import, sort and duplicate scans pick the file up: a photo or a
+/// video.
+pub fn is_media(path: &std::path::Path) -> bool {
+    is_allowed(path) || is_video(path)
+}
+
+fn has_extension(path: &std::path::Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            extensions
+                .iter()
+                .any(|known| known.eq_ignore_ascii_case(ext))
+        })
+}
@@ -0,0 +1,110 @@
+//! Files that belong to the same shot and are moved as one: the formats of a
+//! RAW+JPEG pair and sidecars such as `IMG_1234.xmp`, `IMG_1234.CR2.xmp` or
+//! the `MVI_1234.THM` thumbnail of a clip.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::file_formats::{is_media, is_raw, is_sidecar};
+
+#[derive(Debug, Clone)]
+pub struct FileGroup {
+    /// The file the target path is rendered for; the RAW file of a pair
+    pub primary: PathBuf,
+    /// Other formats and sidecars, which follow the primary file
+    pub members: Vec<PathBuf>,
+}
+
+impl FileGroup {
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.primary.as_path()).chain(self.members.iter().map(PathBuf::as_path))
+    }
+
+    /// Where `member` goes once the primary file went to `primary_target`:
+    /// into the same folder, under the same new stem and with its own ending,
+    /// so `IMG_1234.CR2.xmp` follows `IMG_1234.CR2` to `2024_0001.CR2.xmp`.
+    pub fn member_target(&self, member: &Path, primary_target: &Path) -> PathBuf {
+        let stem_len = file_stem(&self.primary).len();
+        let name = member.file_name().unwrap_or_default().to_string_lossy();
+        let ending = name.get(stem_len..).unwrap_or_default();
+        let target_stem = primary_target
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        primary_target.with_file_name(format!("{}{}", target_stem, ending))
+    }
+}
+
+/// Groups `files` by folder and stem and adds the sidecars found next to
+/// them. Groups keep the order of their first file; sidecars without a photo
+/// are left out.
+pub fn group_files(files: Vec<PathBuf>) -> Vec<FileGroup> {
+    let mut order: Vec<(PathBuf, String)> = Vec::new();
+    let mut groups: HashMap<(PathBuf, String), Vec<PathBuf>> = HashMap::new();
+    for file in files.into_iter().filter(|f| !is_sidecar(f)) {
+        let key = (parent(&file), key(&file));
+        let group = groups.entry(key.clone()).or_default();
+        if group.is_empty() {
+            order.push(key);
+        }
+        group.push(file);
+    }
+
+    let mut folders: Vec<&PathBuf> = order.iter().map(|(folder, _)| folder).collect();
+    folders.sort();
+    folders.dedup();
+    let mut sidecars: HashMap<(PathBuf, String), Vec<PathBuf>> = HashMap::new();
+    for folder in folders {
+        let Ok(entries) = fs::read_dir(folder) else {
+            continue;
+        };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if is_sidecar(&path) && path.is_file() {
+                sidecars
+                    .entry((folder.clone(), key(&path)))
+                    .or_default()
+                    .push(path);
+            }
+        }
+    }
+
+    order
+        .into_iter()
+        .map(|key| {
+            let mut files = groups.remove(&key).unwrap_or_default();
+            let primary = files.iter().position(|f| is_raw(f)).unwrap_or(0);
+            let primary = files.remove(primary);
+            let mut extra = sidecars.remove(&key).unwrap_or_default();
+            extra.sort();
+            files.extend(extra);
+            FileGroup {
+                primary,
+                members: files,
+            }
+        })
+        .collect()
+}
+
+fn parent(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The stem shared by a group, ignoring case. A sidecar named after the full
+/// file name, like `IMG_1234.CR2.xmp`, loses the inner extension as well.
+fn key(path: &Path) -> String {
+    let stem = Path::new(path.file_stem().unwrap_or_default());
+    let stem = if is_sidecar(path) && is_media(stem) {
+        stem.file_stem().unwrap_or_default()
+    } else {
+        stem.as_os_str()
+    };
+    stem.to_string_lossy().to_ascii_lowercase()
+}
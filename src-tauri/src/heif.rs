@@ -0,0 +1,35 @@
+//! Decodes HEIC/HEIF photos, which the `image` crate cannot, through libheif.
+//! Only built with the `heif` feature; without it `decode` finds nothing and
+//! these photos are compared by their bytes alone.
+
+use image::DynamicImage;
+
+/// Decodes the primary image of a HEIF file. libheif applies the rotation
+/// and mirroring stored in the file, so the image comes out upright.
+#[cfg(feature = "heif")]
+pub fn decode(buffer: &[u8]) -> Option<DynamicImage> {
+    use image::RgbImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(buffer).ok()?;
+    let handle = context.primary_image_handle().ok()?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .ok()?;
+    let plane = decoded.planes().interleaved?;
+    // Rows may be padded beyond three bytes per pixel
+    let row_len = plane.width as usize * 3;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(plane.height as usize)
+        .flat_map(|row| row.get(..row_len).unwrap_or_default())
+        .copied()
+        .collect();
+    RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::ImageRgb8)
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn decode(_buffer: &[u8]) -> Option<DynamicImage> {
+    None
+}
@@ -1,128 +1,483 @@
-use chrono::prelude::*;
+use rayon::prelude::*;
 use serde::Serialize;
-use std::{fs, path::PathBuf};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
 use tauri::Emitter;
 use walkdir::WalkDir;
 
-use crate::file_formats::ALLOWED_EXTENSIONS;
+use super::ledger::{self, LedgerEntry, SourceKey};
+use crate::copy_engine;
+use crate::duplicate::full_hash;
+use crate::file_groups::{group_files, FileGroup};
+use crate::journal::Transaction;
+use crate::media_type::detect;
+use crate::placement::{self, ConflictPolicy, PlacementOutcome, PlacementResult, Transfer};
+use crate::template::{PathTemplate, Sequence};
 
-pub async fn import_device(device_path: String, dest_path: String) -> Result<(), String> {
+pub async fn import_device(
+    device_path: String,
+    dest_path: String,
+    template: Option<String>,
+    conflict: ConflictPolicy,
+    verify: bool,
+    only_new: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    let template = PathTemplate::or_default(template.as_deref())?;
     tauri::async_runtime::spawn_blocking(move || {
-        do_import(PathBuf::from(device_path), PathBuf::from(dest_path))
+        do_import(
+            PathBuf::from(device_path),
+            PathBuf::from(dest_path),
+            template,
+            conflict,
+            verify,
+            only_new,
+        )
     })
     .await
-    .map_err(|e| e.to_string())??;
-    Ok(())
+    .map_err(|e| e.to_string())?
 }
 
+/// How often progress is reported while files are being copied.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
 #[derive(Serialize, Clone)]
 pub struct ImportProgress {
     pub total: usize,
+    /// Files done, whether copied or not
     pub copied: usize,
     pub current: String,
+    pub bytes_total: u64,
+    /// Bytes copied plus the size of files that needed no copy
+    pub bytes_done: u64,
+    /// Copied bytes per second since the import started
+    pub bytes_per_second: f64,
+    /// Seconds left at the current rate, unknown until something was copied
+    pub eta_seconds: Option<f64>,
 }
 
 pub async fn import_device_stream(
     window: tauri::Window,
     device_path: String,
     dest_path: String,
-) -> Result<(), String> {
+    template: Option<String>,
+    conflict: ConflictPolicy,
+    verify: bool,
+    only_new: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    let template = PathTemplate::or_default(template.as_deref())?;
     tauri::async_runtime::spawn_blocking(move || {
-        do_import_stream(window, PathBuf::from(device_path), PathBuf::from(dest_path))
+        do_import_stream(
+            window,
+            PathBuf::from(device_path),
+            PathBuf::from(dest_path),
+            template,
+            conflict,
+            verify,
+            only_new,
+        )
     })
     .await
-    .map_err(|e| e.to_string())??;
-    Ok(())
+    .map_err(|e| e.to_string())?
+}
+
+fn do_import(
+    device: PathBuf,
+    dest: PathBuf,
+    template: PathTemplate,
+    conflict: ConflictPolicy,
+    verify: bool,
+    only_new: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    run_import(device, dest, template, conflict, verify, only_new, |_| {})
+}
+
+fn do_import_stream(
+    window: tauri::Window,
+    device: PathBuf,
+    dest: PathBuf,
+    template: PathTemplate,
+    conflict: ConflictPolicy,
+    verify: bool,
+    only_new: bool,
+) -> Result<Vec<PlacementResult>, String> {
+    run_import(
+        device,
+        dest,
+        template,
+        conflict,
+        verify,
+        only_new,
+        |progress| {
+            let _ = window.emit("import_progress", progress);
+        },
+    )
 }
 
-fn do_import(device: PathBuf, dest: PathBuf) -> Result<(), String> {
+fn run_import(
+    device: PathBuf,
+    dest: PathBuf,
+    template: PathTemplate,
+    conflict: ConflictPolicy,
+    verify: bool,
+    only_new: bool,
+    mut on_progress: impl FnMut(ImportProgress) + Send,
+) -> Result<Vec<PlacementResult>, String> {
+    let mut files = Vec::new();
     for entry in WalkDir::new(&device).into_iter().filter_map(|e| e.ok()) {
-        if !entry.file_type().is_file() {
-            continue;
+        if entry.file_type().is_file() && detect(entry.path()).is_some() {
+            files.push(entry.into_path());
         }
-        let ext = entry
-            .path()
-            .extension()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_lowercase());
-        if let Some(ext) = ext {
-            if !ALLOWED_EXTENSIONS
-                .iter()
-                .any(|ext_ok| ext_ok.eq_ignore_ascii_case(&ext))
-            {
-                continue;
+    }
+    let groups = group_files(files);
+    let total = groups.iter().map(|group| group.files().count()).sum();
+    let bytes_total = groups
+        .iter()
+        .flat_map(FileGroup::files)
+        .filter_map(|file| file.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    let workers = copy_engine::workers(&device, &dest);
+
+    let run = ImportRun {
+        transaction: Transaction::begin("import_device", dest.display().to_string()),
+        device_id: ledger::device_identity(&device),
+        device,
+        dest,
+        template,
+        conflict,
+        verify,
+        only_new,
+        files_done: AtomicUsize::new(0),
+        bytes_copied: AtomicU64::new(0),
+        bytes_skipped: AtomicU64::new(0),
+        current: Mutex::new(String::new()),
+    };
+    let (lanes, unplanned) = run.plan(groups);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let progress = || run.progress(total, bytes_total, started);
+    let lanes = std::thread::scope(|scope| {
+        // Reports until the sender is dropped, then once more with the final numbers
+        let (finished, ticks) = mpsc::channel::<()>();
+        scope.spawn(move || loop {
+            let last = matches!(
+                ticks.recv_timeout(PROGRESS_INTERVAL),
+                Err(mpsc::RecvTimeoutError::Disconnected)
+            );
+            on_progress(progress());
+            if last {
+                break;
             }
-        } else {
-            continue;
+        });
+        let lanes = pool.install(|| {
+            lanes
+                .into_par_iter()
+                .map(|lane| run.import_lane(lane))
+                .collect::<Vec<_>>()
+        });
+        drop(finished);
+        lanes
+    });
+
+    let mut results: Vec<(usize, Vec<PlacementResult>)> =
+        lanes.into_iter().flatten().chain(unplanned).collect();
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results
+        .into_iter()
+        .flat_map(|(_, results)| results)
+        .collect())
+}
+
+/// The results of a group, numbered in the order of the card.
+type GroupResults = (usize, Vec<PlacementResult>);
+
+/// A group and where its primary file goes, numbered in the order of the card.
+struct PlannedGroup {
+    index: usize,
+    group: FileGroup,
+    target: PathBuf,
+}
+
+/// What all files of one import share.
+struct ImportRun {
+    transaction: Transaction,
+    device: PathBuf,
+    /// See `ledger::device_identity`
+    device_id: String,
+    dest: PathBuf,
+    template: PathTemplate,
+    conflict: ConflictPolicy,
+    verify: bool,
+    /// Skip files the import ledger knows, wherever they are now
+    only_new: bool,
+    files_done: AtomicUsize,
+    /// Bytes written by the copies so far; retried copies count twice
+    bytes_copied: AtomicU64,
+    /// Size of the files that were not copied, e.g. because they were there
+    bytes_skipped: AtomicU64,
+    current: Mutex<String>,
+}
+
+impl ImportRun {
+    /// Renders the targets in order, so sequence numbers follow the card, and
+    /// sorts the groups into lanes that can be copied in parallel. Groups with
+    /// the same target share a lane, see `lane_key`. Groups whose target
+    /// cannot be rendered are returned as failed right away.
+    fn plan(&self, groups: Vec<FileGroup>) -> (Vec<Vec<PlannedGroup>>, Vec<GroupResults>) {
+        let mut sequence = Sequence::default();
+        let mut lanes: Vec<Vec<PlannedGroup>> = Vec::new();
+        let mut failed = Vec::new();
+        let mut lane_of: HashMap<(PathBuf, String), usize> = HashMap::new();
+        for (index, group) in groups.into_iter().enumerate() {
+            let target = match self.target(&group.primary, &mut sequence) {
+                Ok(target) => target,
+                Err(e) => {
+                    failed.push((index, self.group_failed(&group, &e)));
+                    continue;
+                }
+            };
+            let lane = *lane_of.entry(lane_key(&target)).or_insert_with(|| {
+                lanes.push(Vec::new());
+                lanes.len() - 1
+            });
+            lanes[lane].push(PlannedGroup {
+                index,
+                group,
+                target,
+            });
         }
+        (lanes, failed)
+    }
 
-        let metadata = entry.metadata().map_err(|e| e.to_string())?;
-        let mtime = metadata.modified().map_err(|e| e.to_string())?;
-        let datetime: DateTime<Local> = mtime.into();
-        let year = datetime.format("%Y").to_string();
-        let day = datetime.format("%Y-%m-%d").to_string();
-        let target_dir = dest.join(year).join(day);
-        fs::create_dir_all(&target_dir).map_err(|e| e.to_string())?;
-        let file_name = entry.file_name();
-        let target = target_dir.join(file_name);
-        if !target.exists() {
-            fs::copy(entry.path(), &target).map_err(|e| e.to_string())?;
+    /// Where the template puts `src`. A file the ledger knows by its source
+    /// is not imported again, so it gets no sequence number.
+    fn target(&self, src: &Path, sequence: &mut Sequence) -> io::Result<PathBuf> {
+        if self.only_new {
+            let key = SourceKey::new(&self.device_id, &self.device, src)?;
+            let known = ledger::with_ledger(|ledger| ledger.find_source(&key).cloned())?;
+            if let Some(entry) = known {
+                return Ok(entry.target);
+            }
         }
+        Ok(self.dest.join(self.template.render(src, sequence)?))
     }
 
-    Ok(())
-}
+    fn import_lane(&self, lane: Vec<PlannedGroup>) -> Vec<GroupResults> {
+        lane.into_iter()
+            .map(|planned| {
+                *self.current.lock().unwrap_or_else(|e| e.into_inner()) =
+                    planned.group.primary.display().to_string();
+                let results = self.import_group(&planned.group, planned.target);
+                self.files_done.fetch_add(results.len(), Ordering::Relaxed);
+                (planned.index, results)
+            })
+            .collect()
+    }
 
-fn do_import_stream(window: tauri::Window, device: PathBuf, dest: PathBuf) -> Result<(), String> {
-    let mut files = Vec::new();
-    for entry in WalkDir::new(&device).into_iter().filter_map(|e| e.ok()) {
-        if !entry.file_type().is_file() {
-            continue;
-        }
-        let ext = entry
-            .path()
-            .extension()
-            .and_then(|s| s.to_str())
-            .map(|s| s.to_lowercase());
-        if let Some(ext) = ext {
-            if !ALLOWED_EXTENSIONS
-                .iter()
-                .any(|ext_ok| ext_ok.eq_ignore_ascii_case(&ext))
-            {
+    /// Imports the primary file of a group, then puts its other files next to
+    /// it, or next to where it was imported before. If the primary file did
+    /// not land, the others are skipped as well. A file that cannot be
+    /// imported is reported as `Failed`, and the import goes on.
+    fn import_group(&self, group: &FileGroup, target: PathBuf) -> Vec<PlacementResult> {
+        let primary = self.import_file_or_fail(&group.primary, target);
+        let primary_target = PathBuf::from(&primary.target);
+        let landed = primary.landed();
+        let mut results = vec![primary];
+        for member in &group.members {
+            if !landed {
+                let size = member.metadata().map(|m| m.len()).unwrap_or_default();
+                self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+                results.push(PlacementResult::member_left(member));
                 continue;
             }
+            let target = group.member_target(member, &primary_target);
+            results.push(self.import_file_or_fail(member, target));
+        }
+        results
+    }
+
+    fn import_file_or_fail(&self, src: &Path, target: PathBuf) -> PlacementResult {
+        self.import_file(src, target).unwrap_or_else(|e| {
+            let size = src.metadata().map(|m| m.len()).unwrap_or_default();
+            self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+            PlacementResult::failed(src, &e)
+        })
+    }
+
+    /// Every file of a group that cannot be imported at all, e.g. because
+    /// its target cannot be rendered.
+    fn group_failed(&self, group: &FileGroup, error: &io::Error) -> Vec<PlacementResult> {
+        self.files_done
+            .fetch_add(group.files().count(), Ordering::Relaxed);
+        group
+            .files()
+            .map(|file| {
+                let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+                self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+                PlacementResult::failed(file, error)
+            })
+            .collect()
+    }
+
+    /// Imports one file to `target`.
+    fn import_file(&self, src: &Path, target: PathBuf) -> io::Result<PlacementResult> {
+        let key = SourceKey::new(&self.device_id, &self.device, src)?;
+        let size = key.size;
+        let mut hash = None;
+        if self.only_new {
+            // The name, size and time on the card are enough to recognise a
+            // file without reading it; the content finds it under any name
+            let known = ledger::with_ledger(|ledger| ledger.find_source(&key).cloned())?;
+            if let Some(entry) = known {
+                self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+                return Ok(already_imported(src, &entry.target));
+            }
+            // Reading the whole file only pays off if its size is known
+            if ledger::with_ledger(|ledger| ledger.has_size(size))? {
+                let content = full_hash(src)?;
+                let known = ledger::with_ledger(|ledger| ledger.find_hash(&content).cloned())?;
+                if let Some(entry) = known {
+                    self.record(key, content, entry.target.clone())?;
+                    self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+                    return Ok(already_imported(src, &entry.target));
+                }
+                hash = Some(content);
+            }
+        }
+
+        if let Some(target_dir) = target.parent() {
+            self.transaction.create_dir_all(target_dir)?;
+        }
+        let result = placement::place(
+            &self.transaction,
+            src,
+            &target,
+            self.conflict,
+            Transfer::Copy,
+            self.verify,
+            &|bytes| {
+                self.bytes_copied.fetch_add(bytes, Ordering::Relaxed);
+            },
+        )?;
+        if matches!(
+            result.outcome,
+            PlacementOutcome::Identical | PlacementOutcome::Skipped
+        ) {
+            self.bytes_skipped.fetch_add(size, Ordering::Relaxed);
+        }
+        if result.landed() {
+            // Unless the copy was verified, the source was not read by this
+            // process; the target is usually on a faster disk than the card
+            let hash = match hash.or_else(|| result.hash.clone()) {
+                Some(hash) => hash,
+                None => full_hash(Path::new(&result.target))?,
+            };
+            self.record(key, hash, PathBuf::from(&result.target))?;
+        }
+        Ok(result)
+    }
+
+    fn record(&self, key: SourceKey, hash: String, target: PathBuf) -> io::Result<()> {
+        ledger::record(LedgerEntry {
+            hash,
+            size: key.size,
+            modified_ns: key.modified_ns,
+            device: key.device,
+            source: key.source,
+            target,
+            imported: chrono::Local::now().to_rfc3339(),
+        })
+    }
+
+    fn progress(&self, total: usize, bytes_total: u64, started: Instant) -> ImportProgress {
+        let copied = self.bytes_copied.load(Ordering::Relaxed);
+        // Retried copies count twice, so the sum may overshoot
+        let bytes_done = (copied + self.bytes_skipped.load(Ordering::Relaxed)).min(bytes_total);
+        let elapsed = started.elapsed().as_secs_f64();
+        let bytes_per_second = if elapsed > 0.0 {
+            copied as f64 / elapsed
         } else {
-            continue;
+            0.0
+        };
+        ImportProgress {
+            total,
+            copied: self.files_done.load(Ordering::Relaxed),
+            current: self
+                .current
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+            bytes_total,
+            bytes_done,
+            bytes_per_second,
+            eta_seconds: (bytes_per_second > 0.0)
+                .then(|| (bytes_total - bytes_done) as f64 / bytes_per_second),
         }
+    }
+}
+
+/// Files rendered to the same name share a lane, so which of them keeps the
+/// name and which one gets a number follows the card. Case is ignored, as on
+/// most cards and on macOS and Windows. Other clashes, like a numbered
+/// `IMG_0001_1.JPG` meeting a file of that name, are settled by placement,
+/// which never replaces a file another lane just copied.
+fn lane_key(target: &Path) -> (PathBuf, String) {
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    (
+        target.parent().map(Path::to_path_buf).unwrap_or_default(),
+        name.to_lowercase(),
+    )
+}
 
-        let metadata = entry.metadata().map_err(|e| e.to_string())?;
-        let mtime = metadata.modified().map_err(|e| e.to_string())?;
-        let datetime: DateTime<Local> = mtime.into();
-        let year = datetime.format("%Y").to_string();
-        let day = datetime.format("%Y-%m-%d").to_string();
-        let target_dir = dest.join(&year).join(&day);
-        fs::create_dir_all(&target_dir).map_err(|e| e.to_string())?;
-        let file_name = entry.file_name();
-        let target = target_dir.join(file_name);
-        if !target.exists() {
-            files.push((entry.path().to_path_buf(), target));
+fn already_imported(src: &Path, target: &Path) -> PlacementResult {
+    PlacementResult {
+        source: src.display().to_string(),
+        target: target.display().to_string(),
+        outcome: PlacementOutcome::AlreadyImported,
+        verification: None,
+        message: None,
+        hash: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_names_get_lanes_of_their_own() {
+        let keys: Vec<_> = [
+            "/lib/2024/IMG_0001.JPG",
+            "/lib/2024/IMG_0002.JPG",
+            "/lib/2024/DSC_0002.JPG",
+            "/lib/2024/2024_0001.JPG",
+            "/lib/2024/2024_0002.JPG",
+        ]
+        .iter()
+        .map(|target| lane_key(Path::new(target)))
+        .collect();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(key), "{:?} shares a lane", key);
         }
     }
 
-    let total = files.len();
-    let mut copied = 0usize;
-    for (src, target) in files {
-        fs::copy(&src, &target).map_err(|e| e.to_string())?;
-        copied += 1;
-        let _ = window.emit(
-            "import_progress",
-            ImportProgress {
-                total,
-                copied,
-                current: src.display().to_string(),
-            },
+    #[test]
+    fn equal_targets_share_a_lane() {
+        assert_eq!(
+            lane_key(Path::new("/lib/2024/IMG_0001.JPG")),
+            lane_key(Path::new("/lib/2024/img_0001.jpg"))
+        );
+        assert_ne!(
+            lane_key(Path::new("/lib/2024/IMG_0001.JPG")),
+            lane_key(Path::new("/lib/2025/IMG_0001.JPG"))
         );
     }
-
-    Ok(())
 }
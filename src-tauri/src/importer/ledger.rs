@@ -0,0 +1,253 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use sysinfo::Disks;
+use tempfile::NamedTempFile;
+
+use crate::copy_engine::disk_of;
+
+/// One file imported from a device. Appended to the ledger for every import,
+/// so the same photo is recognised when the card is inserted again, even
+/// after the library was sorted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    /// BLAKE3 hash of the content
+    pub hash: String,
+    pub size: u64,
+    pub modified_ns: u64,
+    /// See `device_identity`
+    pub device: String,
+    /// Path on the device, relative to its mount point
+    pub source: String,
+    /// Where the file was put, updated by `reconcile_ledger`
+    pub target: PathBuf,
+    pub imported: String,
+}
+
+/// Identifies a file on a device without reading it. Cameras keep the
+/// capture time as modification time, so a reused name on a reformatted card
+/// still differs.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SourceKey {
+    pub device: String,
+    pub source: String,
+    pub size: u64,
+    pub modified_ns: u64,
+}
+
+impl SourceKey {
+    pub fn new(device_id: &str, device: &Path, path: &Path) -> io::Result<SourceKey> {
+        let metadata = fs::metadata(path)?;
+        let modified_ns = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Ok(SourceKey {
+            device: device_id.to_string(),
+            source: path
+                .strip_prefix(device)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .into_owned(),
+            size: metadata.len(),
+            modified_ns,
+        })
+    }
+
+    fn of(entry: &LedgerEntry) -> SourceKey {
+        SourceKey {
+            device: entry.device.clone(),
+            source: entry.source.clone(),
+            size: entry.size,
+            modified_ns: entry.modified_ns,
+        }
+    }
+}
+
+/// The ledger in memory, with the index of the newest entry per source and
+/// per content, and the sizes of all entries.
+#[derive(Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+    by_source: HashMap<SourceKey, usize>,
+    by_hash: HashMap<String, usize>,
+    sizes: HashSet<u64>,
+}
+
+impl Ledger {
+    fn push(&mut self, entry: LedgerEntry) {
+        let index = self.entries.len();
+        self.by_source.insert(SourceKey::of(&entry), index);
+        self.by_hash.insert(entry.hash.clone(), index);
+        self.sizes.insert(entry.size);
+        self.entries.push(entry);
+    }
+
+    pub fn find_source(&self, key: &SourceKey) -> Option<&LedgerEntry> {
+        self.by_source.get(key).map(|&i| &self.entries[i])
+    }
+
+    pub fn find_hash(&self, hash: &str) -> Option<&LedgerEntry> {
+        self.by_hash.get(hash).map(|&i| &self.entries[i])
+    }
+
+    /// Whether any file of this size was imported; only then can a file
+    /// have been imported before under another name.
+    pub fn has_size(&self, size: u64) -> bool {
+        self.sizes.contains(&size)
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    /// Changes a copy of the entries and writes it to `path` through a
+    /// temporary file. Only once it is there does the ledger take it over,
+    /// so on failure both stay as they were.
+    fn rewrite(&mut self, path: &Path, f: impl FnOnce(&mut [LedgerEntry])) -> io::Result<()> {
+        let mut entries = self.entries.clone();
+        f(&mut entries);
+        let mut bytes = Vec::new();
+        for entry in &entries {
+            serde_json::to_writer(&mut bytes, entry)?;
+            bytes.push(b'\n');
+        }
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let mut tmp = NamedTempFile::new_in(dir)?;
+        tmp.write_all(&bytes)?;
+        tmp.persist(path)?;
+        *self = Ledger::default();
+        for entry in entries {
+            self.push(entry);
+        }
+        Ok(())
+    }
+}
+
+/// Loaded on first use.
+static LEDGER: Lazy<Mutex<Option<Ledger>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn ledger_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("imagemami")
+        .join("import_ledger.jsonl")
+}
+
+/// Runs `f` on the ledger, reading it from disk first if necessary.
+pub fn with_ledger<T>(f: impl FnOnce(&mut Ledger) -> T) -> io::Result<T> {
+    let mut ledger = LEDGER.lock().unwrap_or_else(|e| e.into_inner());
+    if ledger.is_none() {
+        *ledger = Some(load()?);
+    }
+    Ok(f(ledger.as_mut().expect("loaded above")))
+}
+
+/// Lines that do not parse, like a half-written last line, are skipped.
+fn load() -> io::Result<Ledger> {
+    let mut ledger = Ledger::default();
+    let file = match File::open(ledger_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(ledger),
+        Err(e) => return Err(e),
+    };
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        if let Ok(entry) = serde_json::from_str(&line) {
+            ledger.push(entry);
+        }
+    }
+    Ok(ledger)
+}
+
+/// Appends an entry to the ledger file and the index.
+pub fn record(entry: LedgerEntry) -> io::Result<()> {
+    let mut line = serde_json::to_vec(&entry)?;
+    line.push(b'\n');
+    with_ledger(|ledger| {
+        let path = ledger_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(&line)?;
+        ledger.push(entry);
+        Ok(())
+    })?
+}
+
+/// Changes entries in place and writes the whole ledger anew, atomically.
+pub fn update(f: impl FnOnce(&mut [LedgerEntry])) -> io::Result<()> {
+    with_ledger(|ledger| ledger.rewrite(&ledger_path(), f))?
+}
+
+/// Names a device independent of where it is mounted: the volume label and
+/// capacity of the disk it is on. Falls back to the path if no disk is found.
+pub fn device_identity(device: &Path) -> String {
+    let disks = Disks::new_with_refreshed_list();
+    disk_of(&disks, device)
+        .map(|disk| {
+            let label = disk
+                .mount_point()
+                .file_name()
+                .unwrap_or(disk.name())
+                .to_string_lossy();
+            format!("{}:{}", label, disk.total_space())
+        })
+        .unwrap_or_else(|| device.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, target: &str) -> LedgerEntry {
+        LedgerEntry {
+            hash: format!("hash of {}", source),
+            size: 5,
+            modified_ns: 1,
+            device: "CARD:1000".to_string(),
+            source: source.to_string(),
+            target: PathBuf::from(target),
+            imported: String::new(),
+        }
+    }
+
+    #[test]
+    fn a_failed_rewrite_leaves_the_ledger_as_it_was() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ledger = Ledger::default();
+        ledger.push(entry("IMG_0001.JPG", "/lib/IMG_0001.JPG"));
+        ledger.push(entry("IMG_0002.JPG", "/lib/IMG_0002.JPG"));
+
+        // A file where the folder of the ledger should be
+        fs::write(dir.path().join("data"), "").unwrap();
+        let blocked = dir.path().join("data").join("import_ledger.jsonl");
+        let moved = |entries: &mut [LedgerEntry]| {
+            entries[1].target = PathBuf::from("/lib/2024/IMG_0002.JPG");
+        };
+        assert!(ledger.rewrite(&blocked, moved).is_err());
+        let second = ledger.find_hash("hash of IMG_0002.JPG").unwrap();
+        assert_eq!(second.target, Path::new("/lib/IMG_0002.JPG"));
+
+        let path = dir.path().join("import_ledger.jsonl");
+        ledger.rewrite(&path, moved).unwrap();
+        let second = ledger.find_hash("hash of IMG_0002.JPG").unwrap();
+        assert_eq!(second.target, Path::new("/lib/2024/IMG_0002.JPG"));
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 2);
+        assert!(written.contains("/lib/2024/IMG_0002.JPG"));
+        let leftovers = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(leftovers, 2, "only the blocking file and the ledger");
+    }
+}
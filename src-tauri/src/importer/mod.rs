@@ -1,17 +1,44 @@
 mod external_device;
 mod list_external_devices;
 mod import_device;
+mod ledger;
+mod reconcile_ledger;
 
 pub use external_device::ExternalDevice;
+pub use ledger::LedgerEntry;
+pub use reconcile_ledger::ReconcileReport;
+
+use std::path::Path;
+
+use crate::placement::{ConflictPolicy, PlacementResult};
 
 #[tauri::command]
 pub fn list_external_devices() -> Result<Vec<ExternalDevice>, String> {
     list_external_devices::list_external_devices()
 }
 
+/// Copies the images of a device to where `template` puts them below `dest_path`.
+/// `conflict` decides about images whose target is taken by a different file;
+/// with `verify` set, every copy is checked against its source. With `only_new`
+/// set, images the import ledger knows from an earlier import are skipped.
 #[tauri::command]
-pub async fn import_device(device_path: String, dest_path: String) -> Result<(), String> {
-    import_device::import_device(device_path, dest_path).await
+pub async fn import_device(
+    device_path: String,
+    dest_path: String,
+    template: Option<String>,
+    conflict: Option<ConflictPolicy>,
+    verify: Option<bool>,
+    only_new: Option<bool>,
+) -> Result<Vec<PlacementResult>, String> {
+    import_device::import_device(
+        device_path,
+        dest_path,
+        template,
+        conflict.unwrap_or_default(),
+        verify.unwrap_or(false),
+        only_new.unwrap_or(false),
+    )
+    .await
 }
 
 #[tauri::command]
@@ -19,6 +46,30 @@ pub async fn import_device_stream(
     window: tauri::Window,
     device_path: String,
     dest_path: String,
-) -> Result<(), String> {
-    import_device::import_device_stream(window, device_path, dest_path).await
+    template: Option<String>,
+    conflict: Option<ConflictPolicy>,
+    verify: Option<bool>,
+    only_new: Option<bool>,
+) -> Result<Vec<PlacementResult>, String> {
+    import_device::import_device_stream(
+        window,
+        device_path,
+        dest_path,
+        template,
+        conflict.unwrap_or_default(),
+        verify.unwrap_or(false),
+        only_new.unwrap_or(false),
+    )
+    .await
+}
+
+/// Finds imported files that were moved or renamed since by their content in
+/// `library`, so the import ledger points to where they are now.
+#[tauri::command]
+pub async fn reconcile_ledger(library: String) -> Result<ReconcileReport, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        reconcile_ledger::reconcile_ledger(Path::new(&library))
+    })
+    .await
+    .map_err(|e| e.to_string())?
 }
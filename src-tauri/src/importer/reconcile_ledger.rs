@@ -0,0 +1,77 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::ledger;
+use crate::duplicate::full_hash;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReconcileReport {
+    /// Entries in the ledger
+    pub entries: usize,
+    /// Entries whose file was found again at a new path
+    pub relocated: usize,
+    /// Last known paths of imported files that are nowhere in the library,
+    /// e.g. because they were deleted
+    pub missing: Vec<String>,
+}
+
+/// Looks for imported files that are no longer where the ledger says, e.g.
+/// after sorting or renaming, and finds them in `library` by content. Only
+/// files of a size the ledger is missing are hashed.
+pub fn reconcile_ledger(library: &Path) -> Result<ReconcileReport, String> {
+    let (entries, lost) = ledger::with_ledger(|ledger| {
+        let lost: Vec<(String, u64)> = ledger
+            .entries()
+            .iter()
+            .filter(|entry| !entry.target.exists())
+            .map(|entry| (entry.hash.clone(), entry.size))
+            .collect();
+        (ledger.entries().len(), lost)
+    })
+    .map_err(|e| e.to_string())?;
+    if lost.is_empty() {
+        return Ok(ReconcileReport {
+            entries,
+            relocated: 0,
+            missing: Vec::new(),
+        });
+    }
+    let sizes: HashSet<u64> = lost.iter().map(|(_, size)| *size).collect();
+    let wanted: HashSet<&str> = lost.iter().map(|(hash, _)| hash.as_str()).collect();
+
+    let mut found: HashMap<String, PathBuf> = HashMap::new();
+    for entry in WalkDir::new(library).into_iter().filter_map(|e| e.ok()) {
+        let matches_size = entry
+            .metadata()
+            .is_ok_and(|m| m.is_file() && sizes.contains(&m.len()));
+        if !matches_size {
+            continue;
+        }
+        if let Ok(hash) = full_hash(entry.path()) {
+            if wanted.contains(hash.as_str()) {
+                found.entry(hash).or_insert_with(|| entry.into_path());
+            }
+        }
+    }
+
+    let mut report = ReconcileReport {
+        entries,
+        relocated: 0,
+        missing: Vec::new(),
+    };
+    ledger::update(|entries| {
+        for entry in entries.iter_mut().filter(|entry| !entry.target.exists()) {
+            match found.get(&entry.hash) {
+                Some(path) => {
+                    entry.target = path.clone();
+                    report.relocated += 1;
+                }
+                None => report.missing.push(entry.target.display().to_string()),
+            }
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(report)
+}
@@ -0,0 +1,65 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+use super::transaction::{read_all, Operation, Record};
+
+/// A transaction as shown in the history.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionSummary {
+    pub id: String,
+    pub name: String,
+    pub detail: String,
+    pub started: String,
+    pub operations: usize,
+    /// When it was undone, if it was
+    pub undone: Option<String>,
+    /// Files removed for good, which an undo cannot bring back
+    pub irreversible: usize,
+}
+
+/// All recorded transactions, newest first.
+pub fn list_operations() -> Result<Vec<TransactionSummary>, String> {
+    let mut order = Vec::new();
+    let mut summaries: HashMap<String, TransactionSummary> = HashMap::new();
+    for record in read_all().map_err(|e| e.to_string())? {
+        let summary = summaries
+            .entry(record.transaction().to_string())
+            .or_insert_with_key(|id| {
+                order.push(id.clone());
+                TransactionSummary {
+                    id: id.clone(),
+                    name: String::new(),
+                    detail: String::new(),
+                    started: String::new(),
+                    operations: 0,
+                    undone: None,
+                    irreversible: 0,
+                }
+            });
+        match record {
+            Record::Begin {
+                name,
+                detail,
+                started,
+                ..
+            } => {
+                summary.name = name;
+                summary.detail = detail;
+                summary.started = started;
+            }
+            Record::Operation { operation, .. } => {
+                summary.operations += 1;
+                if let Operation::Delete { .. } = operation {
+                    summary.irreversible += 1;
+                }
+            }
+            Record::Reversed { .. } => {}
+            Record::Undone { at, .. } => summary.undone = Some(at),
+        }
+    }
+    Ok(order
+        .into_iter()
+        .rev()
+        .filter_map(|id| summaries.remove(&id))
+        .collect())
+}
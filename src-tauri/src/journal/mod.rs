@@ -0,0 +1,21 @@
+mod list_operations;
+mod transaction;
+mod undo_operation;
+
+pub use list_operations::TransactionSummary;
+pub use transaction::{Operation, Transaction};
+pub use undo_operation::{UndoFailure, UndoReport};
+
+/// Every recorded transaction, newest first.
+#[tauri::command]
+pub fn list_operations() -> Result<Vec<TransactionSummary>, String> {
+    list_operations::list_operations()
+}
+
+/// Reverses a whole transaction, e.g. a sort or a blackhole import.
+#[tauri::command]
+pub async fn undo_operation(id: String) -> Result<UndoReport, String> {
+    tauri::async_runtime::spawn_blocking(move || undo_operation::undo_operation(&id))
+        .await
+        .map_err(|e| e.to_string())?
+}
@@ -0,0 +1,250 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use crate::copy_engine;
+
+/// One change to the file system, recorded after it succeeded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation {
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// Removed for good; the only operation that cannot be undone
+    Delete {
+        path: PathBuf,
+    },
+    /// Moved to the trash as `trashed`, described by the `.trashinfo` file `info`
+    Trash {
+        path: PathBuf,
+        trashed: PathBuf,
+        info: PathBuf,
+    },
+    /// Moved to the trash of macOS or Windows, which keeps track of where
+    /// the file went itself
+    SystemTrash {
+        path: PathBuf,
+    },
+    /// Replaced by a link to `keeper` with the same content
+    Link {
+        path: PathBuf,
+        keeper: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+}
+
+/// A line of the journal file. Transactions are interleaved, so every line
+/// names the transaction it belongs to.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Record {
+    Begin {
+        transaction: String,
+        /// The command that ran, e.g. `sort_images`
+        name: String,
+        /// The folder or file the command worked on
+        detail: String,
+        started: String,
+    },
+    Operation {
+        transaction: String,
+        operation: Operation,
+    },
+    /// One operation, by its position within the transaction, was reversed.
+    /// A later undo skips it, so an undo that partly failed can be retried.
+    Reversed {
+        transaction: String,
+        index: usize,
+    },
+    /// Every operation was reversed
+    Undone {
+        transaction: String,
+        at: String,
+    },
+}
+
+impl Record {
+    pub fn transaction(&self) -> &str {
+        match self {
+            Record::Begin { transaction, .. }
+            | Record::Operation { transaction, .. }
+            | Record::Reversed { transaction, .. }
+            | Record::Undone { transaction, .. } => transaction,
+        }
+    }
+}
+
+/// The journal file, opened for appending on first use.
+static JOURNAL: Lazy<Mutex<Option<File>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn journal_path() -> PathBuf {
+    dirs::data_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("imagemami")
+        .join("journal.jsonl")
+}
+
+/// Appends a record. Each record is one line written at once, so a crash can
+/// at most lose the last line, never corrupt earlier ones.
+pub fn append(record: &Record) -> io::Result<()> {
+    let mut journal = JOURNAL.lock().unwrap_or_else(|e| e.into_inner());
+    if journal.is_none() {
+        let path = journal_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        *journal = Some(OpenOptions::new().create(true).append(true).open(path)?);
+    }
+    let mut line = serde_json::to_vec(record)?;
+    line.push(b'\n');
+    journal.as_mut().expect("opened above").write_all(&line)
+}
+
+/// Reads every record; lines that do not parse, like a half-written last
+/// line, are skipped.
+pub fn read_all() -> io::Result<Vec<Record>> {
+    let file = match File::open(journal_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str(&line).ok())
+        .collect())
+}
+
+pub fn now() -> String {
+    chrono::Local::now().to_rfc3339()
+}
+
+/// A named group of operations that is undone as a whole. Nothing is written
+/// to the journal until the first operation, so commands that change nothing
+/// leave no trace.
+pub struct Transaction {
+    id: String,
+    name: &'static str,
+    detail: String,
+    begun: AtomicBool,
+}
+
+static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
+impl Transaction {
+    pub fn begin(name: &'static str, detail: impl Into<String>) -> Self {
+        // Unique across restarts through the time, within a run through the counter
+        let id = format!(
+            "{}-{}",
+            chrono::Local::now().format("%Y%m%d%H%M%S%3f"),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        );
+        Transaction {
+            id,
+            name,
+            detail: detail.into(),
+            begun: AtomicBool::new(false),
+        }
+    }
+
+    pub fn record(&self, operation: Operation) -> io::Result<()> {
+        if !self.begun.swap(true, Ordering::SeqCst) {
+            append(&Record::Begin {
+                transaction: self.id.clone(),
+                name: self.name.to_string(),
+                detail: self.detail.clone(),
+                started: now(),
+            })?;
+        }
+        append(&Record::Operation {
+            transaction: self.id.clone(),
+            operation,
+        })
+    }
+
+    /// Like `fs::create_dir_all`, recording every folder it had to create.
+    pub fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let missing: Vec<&Path> = path.ancestors().take_while(|p| !p.is_dir()).collect();
+        for dir in missing.into_iter().rev() {
+            match fs::create_dir(dir) {
+                Ok(()) => self.record(Operation::CreateDir {
+                    path: dir.to_path_buf(),
+                })?,
+                // Created by someone else in the meantime, so not ours to remove
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `copy_engine::rename_file`, failing with `AlreadyExists` rather
+    /// than replacing a file.
+    pub fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        copy_engine::rename_file(from, to)?;
+        self.record(Operation::Move {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        })
+    }
+
+    /// Copies and records it. `on_bytes` is called like in `copy_engine::copy_file`.
+    pub fn copy(&self, from: &Path, to: &Path, on_bytes: &dyn Fn(u64)) -> io::Result<u64> {
+        let copied = self.copy_unrecorded(from, to, on_bytes)?;
+        self.record(Operation::Copy {
+            from: from.to_path_buf(),
+            to: to.to_path_buf(),
+        })?;
+        Ok(copied)
+    }
+
+    /// Moves a file by copying it, which works across volumes. If the source
+    /// cannot be removed afterwards, only the copy is recorded.
+    pub fn copy_and_remove(
+        &self,
+        from: &Path,
+        to: &Path,
+        on_bytes: &dyn Fn(u64),
+    ) -> io::Result<u64> {
+        let copied = self.copy_unrecorded(from, to, on_bytes)?;
+        self.remove_copied(from, to)?;
+        Ok(copied)
+    }
+
+    /// Copies without recording it, for copies that only count once they
+    /// are verified.
+    pub fn copy_unrecorded(
+        &self,
+        from: &Path,
+        to: &Path,
+        on_bytes: &dyn Fn(u64),
+    ) -> io::Result<u64> {
+        copy_engine::copy_file(from, to, on_bytes)
+    }
+
+    /// Removes the source of a finished copy and records the move, or only
+    /// the copy if the source cannot be removed.
+    pub fn remove_copied(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let operation = match fs::remove_file(from) {
+            Ok(()) => Operation::Move {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            },
+            Err(_) => Operation::Copy {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            },
+        };
+        self.record(operation)
+    }
+}
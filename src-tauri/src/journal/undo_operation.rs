@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::transaction::{append, now, read_all, Operation, Record};
+use crate::duplicate::{restore_from_trash, same_content};
+
+/// An operation that could not be reversed, e.g. because the file was changed
+/// or removed since.
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoFailure {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoReport {
+    pub undone: usize,
+    pub failed: Vec<UndoFailure>,
+}
+
+/// Reverses all operations of a transaction, last one first. An operation
+/// that cannot be reversed is reported and the rest still goes ahead; the
+/// transaction only counts as undone once every operation was reversed, so
+/// the failed ones can be tried again.
+pub fn undo_operation(id: &str) -> Result<UndoReport, String> {
+    let records = read_all().map_err(|e| e.to_string())?;
+    let mut operations = Vec::new();
+    let mut reversed = HashSet::new();
+    let mut known = false;
+    for record in records.into_iter().filter(|r| r.transaction() == id) {
+        known = true;
+        match record {
+            Record::Operation { operation, .. } => operations.push(operation),
+            Record::Reversed { index, .. } => {
+                reversed.insert(index);
+            }
+            Record::Undone { .. } => return Err(format!("operation {} was already undone", id)),
+            Record::Begin { .. } => {}
+        }
+    }
+    if !known {
+        return Err(format!("no operation {} in the journal", id));
+    }
+
+    let mut report = UndoReport {
+        undone: 0,
+        failed: Vec::new(),
+    };
+    for (index, operation) in operations.iter().enumerate().rev() {
+        if reversed.contains(&index) {
+            continue;
+        }
+        match reverse(operation) {
+            Ok(()) => {
+                report.undone += 1;
+                append(&Record::Reversed {
+                    transaction: id.to_string(),
+                    index,
+                })
+                .map_err(|e| e.to_string())?;
+            }
+            Err((path, e)) => report.failed.push(UndoFailure {
+                path: path.display().to_string(),
+                message: e.to_string(),
+            }),
+        }
+    }
+    if !report.failed.is_empty() {
+        return Ok(report);
+    }
+    append(&Record::Undone {
+        transaction: id.to_string(),
+        at: now(),
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+fn reverse(operation: &Operation) -> Result<(), (&Path, io::Error)> {
+    match operation {
+        Operation::Move { from, to } => move_back(to, from).map_err(|e| (to.as_path(), e)),
+        Operation::Copy { from, to } => remove_copy(from, to).map_err(|e| (to.as_path(), e)),
+        Operation::Delete { path } => {
+            Err((path, io::Error::other("the file was deleted permanently")))
+        }
+        Operation::Trash {
+            path,
+            trashed,
+            info,
+        } => move_back(trashed, path)
+            .map(|_| {
+                let _ = fs::remove_file(info);
+            })
+            .map_err(|e| (path.as_path(), e)),
+        Operation::SystemTrash { path } => {
+            restore_from_trash(path).map_err(|e| (path.as_path(), e))
+        }
+        Operation::Link { path, keeper } => unlink(path, keeper).map_err(|e| (path.as_path(), e)),
+        // Fails on purpose if something else was put into the folder since
+        Operation::CreateDir { path } => fs::remove_dir(path).map_err(|e| (path.as_path(), e)),
+    }
+}
+
+/// Moves `from` back to `to`, never overwriting anything that took its place.
+fn move_back(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match fs::rename(from, to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
+/// Removes a copy, but only while the original still exists with the same
+/// content, so the copy is never the last one left.
+fn remove_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let original_kept = match same_content(from, to) {
+        Ok(same) => same,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && fs::symlink_metadata(to).is_ok() => false,
+        Err(e) => return Err(e),
+    };
+    if !original_kept {
+        return Err(io::Error::other(format!(
+            "the original '{}' is gone or was changed, so the copy is kept",
+            from.display()
+        )));
+    }
+    fs::remove_file(to)
+}
+
+/// Turns a link back into a file of its own with the keeper's content.
+fn unlink(path: &Path, keeper: &Path) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp: PathBuf = path.with_file_name(format!(".{}.undo-tmp", name));
+    fs::copy(keeper, &temp)?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temp);
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_are_only_removed_while_the_original_is_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original.jpg");
+        let copy = dir.path().join("copy.jpg");
+        fs::write(&original, "photo").unwrap();
+        fs::write(&copy, "photo").unwrap();
+
+        fs::write(&original, "edited photo").unwrap();
+        assert!(remove_copy(&original, &copy).is_err());
+        fs::remove_file(&original).unwrap();
+        assert!(remove_copy(&original, &copy).is_err());
+        assert!(copy.exists());
+
+        fs::write(&original, "photo").unwrap();
+        remove_copy(&original, &copy).unwrap();
+        assert!(!copy.exists());
+    }
+}
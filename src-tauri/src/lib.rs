@@ -1,12 +1,33 @@
 mod blackhole;
+mod capture_date;
+mod copy_engine;
 mod duplicate;
+mod exif;
 mod file_formats;
+mod file_groups;
+mod heif;
 mod importer;
+mod journal;
+mod media_type;
+mod placement;
+mod quicktime;
+mod raw_preview;
 mod sort;
+mod template;
 
-pub use duplicate::{CompareMethod, DuplicateMatches, FileInfo, MatchPair, ScanConfig};
-pub use file_formats::ALLOWED_EXTENSIONS;
-pub use importer::ExternalDevice;
+pub use duplicate::{
+    CompareMethod, DeleteOutcome, DeleteResult, DuplicateGroupEvent, DuplicateMatches,
+    FileFilter, FileInfo, HashSize, KeeperRule, KeeperSuggestion, LinkKind, LinkOutcome,
+    LinkResult, MatchPair, OrientationMode, PreviewSettings, RestoreOutcome, RestoreResult,
+    ScanConfig, ScanOutcome, ScanRoot, ScanStats, Transform,
+};
+pub use capture_date::{CaptureDate, DateSource};
+pub use file_formats::{ALLOWED_EXTENSIONS, VIDEO_EXTENSIONS};
+pub use importer::{ExternalDevice, LedgerEntry, ReconcileReport};
+pub use journal::{Operation, TransactionSummary, UndoFailure, UndoReport};
+pub use media_type::{ExtensionFix, ExtensionMismatch, FixOutcome, MediaType};
+pub use placement::{ConflictPolicy, PlacementOutcome, PlacementResult, Verification};
+pub use template::{TemplatePreview, DEFAULT_TEMPLATE};
 
 #[tauri::command]
 fn greet(name: &str) -> String {
@@ -22,14 +43,25 @@ pub fn run() {
             greet,
             duplicate::scan_folder_stream_multi,
             duplicate::delete_files,
+            duplicate::link_duplicates,
+            duplicate::restore_files,
             duplicate::cancel_scan,
+            duplicate::rebuild_hash_cache,
+            duplicate::clear_hash_cache,
             importer::list_external_devices,
             importer::import_device,
             importer::import_device_stream,
+            importer::reconcile_ledger,
             blackhole::scan_blackhole_stream,
             blackhole::import_blackhole,
             sort::find_images,
             sort::sort_images,
+            journal::list_operations,
+            journal::undo_operation,
+            media_type::find_extension_mismatches,
+            media_type::fix_extensions,
+            template::validate_template,
+            template::preview_template,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -0,0 +1,72 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::sniff::{detect, MediaType};
+use crate::file_formats::is_media;
+
+/// A file whose extension does not fit its content.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtensionMismatch {
+    pub path: String,
+    /// What the content is; `None` for a file named like a photo or video
+    /// that is something else, e.g. an HTML page saved as `.jpg`
+    pub detected: Option<MediaType>,
+    /// Where `fix_extensions` would rename the file to
+    pub fixed: Option<String>,
+}
+
+/// Lists the media files below `path` with a wrong or missing extension, and
+/// the files that only pretend to be media by their name.
+pub fn find_mismatches(path: &Path) -> Vec<ExtensionMismatch> {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| mismatch(&e.into_path()))
+        .collect()
+}
+
+fn mismatch(path: &Path) -> Option<ExtensionMismatch> {
+    let detected = detect(path);
+    let fits = match detected {
+        Some(media_type) => media_type.matches_extension(path),
+        None => !is_media(path),
+    };
+    if fits {
+        return None;
+    }
+    Some(ExtensionMismatch {
+        path: path.display().to_string(),
+        detected,
+        fixed: detected
+            .and_then(|media_type| fixed_path(path, media_type))
+            .map(|fixed| fixed.display().to_string()),
+    })
+}
+
+/// `path` with the extension of `media_type`. A wrong media extension is
+/// replaced, anything else is kept as part of the name, so `scan.txt` becomes
+/// `scan.txt.jpg` and a date like `2024.05.06` stays intact.
+pub fn fixed_path(path: &Path, media_type: MediaType) -> Option<PathBuf> {
+    let ext = media_type.fixed_extension()?;
+    let name = path.file_name()?.to_string_lossy();
+    Some(if is_media(path) {
+        path.with_extension(fixed_case(path, ext))
+    } else {
+        path.with_file_name(format!("{}.{}", name, ext))
+    })
+}
+
+/// Upper case if the current extension is, like cameras write it.
+fn fixed_case(path: &Path, ext: &str) -> String {
+    let upper = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.chars().any(|c| c.is_ascii_uppercase()));
+    if upper {
+        ext.to_ascii_uppercase()
+    } else {
+        ext.to_string()
+    }
+}
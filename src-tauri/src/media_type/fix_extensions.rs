@@ -0,0 +1,63 @@
+use serde::Serialize;
+use std::path::Path;
+
+use super::find_mismatches::fixed_path;
+use super::sniff::detect;
+use crate::journal::Transaction;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum FixOutcome {
+    /// Renamed to the extension of its content
+    Fixed,
+    /// The extension already fits the content
+    Unchanged,
+    /// Not a known media type, or one without a single right extension
+    Unknown,
+    /// A file with the fixed name exists already
+    TargetExists,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtensionFix {
+    pub path: String,
+    /// The new path of a fixed file
+    pub target: Option<String>,
+    pub outcome: FixOutcome,
+    pub message: Option<String>,
+}
+
+/// Gives each file the extension its content calls for. The renames are
+/// journaled, so they can be undone together.
+pub fn fix_extensions(paths: Vec<String>) -> Vec<ExtensionFix> {
+    let transaction =
+        Transaction::begin("fix_extensions", paths.first().cloned().unwrap_or_default());
+    paths
+        .into_iter()
+        .map(|p| {
+            let result = |outcome, target: Option<&Path>, message| ExtensionFix {
+                path: p.clone(),
+                target: target.map(|t| t.display().to_string()),
+                outcome,
+                message,
+            };
+            let path = Path::new(&p);
+            let Some(media_type) = detect(path) else {
+                return result(FixOutcome::Unknown, None, None);
+            };
+            if media_type.matches_extension(path) {
+                return result(FixOutcome::Unchanged, None, None);
+            }
+            let Some(target) = fixed_path(path, media_type) else {
+                return result(FixOutcome::Unknown, None, None);
+            };
+            if target.symlink_metadata().is_ok() {
+                return result(FixOutcome::TargetExists, Some(&target), None);
+            }
+            match transaction.rename(path, &target) {
+                Ok(()) => result(FixOutcome::Fixed, Some(&target), None),
+                Err(e) => result(FixOutcome::Failed, Some(&target), Some(e.to_string())),
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,23 @@
+mod find_mismatches;
+mod fix_extensions;
+mod sniff;
+
+pub use find_mismatches::ExtensionMismatch;
+pub use fix_extensions::{ExtensionFix, FixOutcome};
+pub use sniff::{detect, detect_bytes, MediaType};
+
+/// Lists the files below `path` whose extension does not fit their content.
+#[tauri::command]
+pub async fn find_extension_mismatches(path: String) -> Result<Vec<ExtensionMismatch>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        find_mismatches::find_mismatches(std::path::Path::new(&path))
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// Renames `paths` to the extension their content calls for.
+#[tauri::command]
+pub fn fix_extensions(paths: Vec<String>) -> Vec<ExtensionFix> {
+    fix_extensions::fix_extensions(paths)
+}
@@ -0,0 +1,177 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Enough for every signature below; MPEG transport streams need two packets.
+const HEADER_BYTES: u64 = 512;
+
+/// Major and compatible brands of HEIF images in an ISO base media `ftyp` box.
+const HEIF_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+/// Box types a QuickTime movie without `ftyp` box starts with.
+const QUICKTIME_BOXES: [&[u8; 4]; 6] = [b"moov", b"mdat", b"wide", b"free", b"skip", b"pnot"];
+
+/// A file format recognised by its content.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum MediaType {
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Webp,
+    /// TIFF and the RAW formats built on it, like DNG, NEF or ARW
+    Tiff,
+    Cr2,
+    Cr3,
+    Rw2,
+    /// Fujifilm RAW
+    Raf,
+    Heif,
+    Mp4,
+    QuickTime,
+    /// AVCHD clips (`.MTS`) and other MPEG transport streams
+    MpegTs,
+    Avi,
+}
+
+impl MediaType {
+    /// The extensions a file of this type may have, the usual one first.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            MediaType::Jpeg => &["jpg", "jpeg"],
+            MediaType::Png => &["png"],
+            MediaType::Gif => &["gif"],
+            MediaType::Bmp => &["bmp"],
+            MediaType::Webp => &["webp"],
+            MediaType::Tiff => &["tif", "tiff", "dng", "nef", "arw", "pef", "sr2", "raw"],
+            MediaType::Cr2 => &["cr2"],
+            MediaType::Cr3 => &["cr3"],
+            MediaType::Rw2 => &["rw2", "raw"],
+            MediaType::Raf => &["raf"],
+            MediaType::Heif => &["heic", "heif"],
+            MediaType::Mp4 => &["mp4", "m4v", "3gp", "mov"],
+            MediaType::QuickTime => &["mov", "mp4"],
+            MediaType::MpegTs => &["mts", "m2ts"],
+            MediaType::Avi => &["avi"],
+        }
+    }
+
+    /// The extension a misnamed file gets. `None` for TIFF, since a RAW file
+    /// of some camera would lose what it is as `.tif`.
+    pub fn fixed_extension(self) -> Option<&'static str> {
+        match self {
+            MediaType::Tiff => None,
+            _ => self.extensions().first().copied(),
+        }
+    }
+
+    /// Whether `path` has one of the extensions of this type.
+    pub fn matches_extension(self, path: &Path) -> bool {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                self.extensions()
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(ext))
+            })
+    }
+
+    pub fn is_video(self) -> bool {
+        matches!(
+            self,
+            MediaType::Mp4 | MediaType::QuickTime | MediaType::MpegTs | MediaType::Avi
+        )
+    }
+}
+
+/// Reads the start of a file and tells which media type it is, whatever its
+/// extension says. `None` for anything else and for files that cannot be read.
+pub fn detect(path: &Path) -> Option<MediaType> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(HEADER_BYTES)
+        .read_to_end(&mut header)
+        .ok()?;
+    detect_bytes(&header)
+}
+
+pub fn detect_bytes(header: &[u8]) -> Option<MediaType> {
+    let at = |range: std::ops::Range<usize>| header.get(range).unwrap_or_default();
+    if header.starts_with(&[0xff, 0xd8, 0xff]) {
+        return Some(MediaType::Jpeg);
+    }
+    if header.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Some(MediaType::Png);
+    }
+    if header.starts_with(b"GIF87a") || header.starts_with(b"GIF89a") {
+        return Some(MediaType::Gif);
+    }
+    if header.starts_with(b"II*\0") || header.starts_with(b"MM\0*") {
+        return Some(if at(8..10) == b"CR" {
+            MediaType::Cr2
+        } else {
+            MediaType::Tiff
+        });
+    }
+    if header.starts_with(b"IIU\0") {
+        return Some(MediaType::Rw2);
+    }
+    if header.starts_with(b"FUJIFILMCCD-RAW ") {
+        return Some(MediaType::Raf);
+    }
+    if header.starts_with(b"RIFF") {
+        return match at(8..12) {
+            b"WEBP" => Some(MediaType::Webp),
+            b"AVI " => Some(MediaType::Avi),
+            _ => None,
+        };
+    }
+    if at(4..8) == b"ftyp" {
+        return Some(iso_media_type(header));
+    }
+    if QUICKTIME_BOXES.iter().any(|&name| at(4..8) == name) {
+        return Some(MediaType::QuickTime);
+    }
+    // Packets of 188 bytes, or 192 with a timestamp in front for `.M2TS`
+    let sync = |offset: usize| header.get(offset) == Some(&0x47);
+    if (sync(0) && sync(188)) || (sync(4) && sync(196)) {
+        return Some(MediaType::MpegTs);
+    }
+    // "BM" alone is too common a start, so the size of the DIB header must fit
+    if header.starts_with(b"BM") {
+        let dib_header = at(14..18).try_into().map(u32::from_le_bytes);
+        if matches!(dib_header, Ok(12 | 40 | 52 | 56 | 108 | 124)) {
+            return Some(MediaType::Bmp);
+        }
+    }
+    None
+}
+
+/// Tells HEIF images from MP4 and QuickTime movies by the brands of the
+/// `ftyp` box: the major brand at 8, compatible brands from 16 to its end.
+fn iso_media_type(header: &[u8]) -> MediaType {
+    let size = header
+        .get(..4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_be_bytes)
+        .unwrap_or_default() as usize;
+    let end = size.clamp(12, header.len().max(12));
+    let compatible = header.get(16..end).unwrap_or_default().chunks_exact(4);
+    let brands: Vec<&[u8]> = header.get(8..12).into_iter().chain(compatible).collect();
+    if brands.first() == Some(&&b"qt  "[..]) {
+        MediaType::QuickTime
+    } else if brands.first() == Some(&&b"crx "[..]) {
+        MediaType::Cr3
+    } else if brands
+        .iter()
+        .any(|brand| HEIF_BRANDS.iter().any(|heif| brand == heif))
+    {
+        MediaType::Heif
+    } else {
+        MediaType::Mp4
+    }
+}
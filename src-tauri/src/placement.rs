@@ -0,0 +1,487 @@
+//! Puts a file at its target path without ever losing one silently. A file
+//! already at the target is compared by content, and a different one is
+//! handled as the `ConflictPolicy` says.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use crate::copy_engine;
+use crate::duplicate::{full_hash, move_to_trash, same_content};
+use crate::file_groups::FileGroup;
+use crate::journal::{Operation, Transaction};
+
+/// How often a copy that differs from its source is made again.
+const VERIFY_RETRIES: u32 = 2;
+
+/// What to do if a different file already has the target name, e.g. after a
+/// camera's counter wrapped around.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Gives the new file a free name like `IMG_0001_1.JPG`
+    #[default]
+    Rename,
+    /// Like `Rename`, but also for files with identical content
+    KeepBoth,
+    /// Replaces the existing file, which goes to the trash
+    Overwrite,
+    /// Leaves the existing file alone and the new one where it is
+    Skip,
+}
+
+/// How the file gets to its target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Copy,
+    /// Copies and removes the source afterwards, which works across volumes
+    CopyAndRemove,
+    Rename,
+}
+
+/// What was decided for one file.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum PlacementOutcome {
+    /// The target was free
+    Placed,
+    /// A file with the same content is already there, so nothing was done
+    Identical,
+    /// Placed under a free name next to the target
+    Renamed,
+    Overwritten,
+    /// A different file is already there and the policy is `Skip`
+    Skipped,
+    /// Imported before according to the import ledger; the target is where
+    /// the file was put then
+    AlreadyImported,
+    /// Placing the file failed as `message` says; it is left where it is
+    Failed,
+}
+
+/// Whether a copy was compared with its source.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum Verification {
+    /// Source and copy have the same BLAKE3 hash
+    Verified,
+    /// The first copy differed, but one of the retries matched
+    Repaired,
+    /// Every copy differed; the last one was removed and the source kept
+    Mismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlacementResult {
+    pub source: String,
+    /// Where the file is now, or the file it was found identical to
+    pub target: String,
+    pub outcome: PlacementOutcome,
+    /// Set if the file was copied with verification on
+    pub verification: Option<Verification>,
+    /// Why the file could not be placed
+    pub message: Option<String>,
+    /// BLAKE3 hash of the source, if it was read for verification anyway
+    #[serde(skip)]
+    pub hash: Option<String>,
+}
+
+/// What `run` and `replace` did besides putting the file in place.
+struct Transferred {
+    verification: Option<Verification>,
+    hash: Option<String>,
+}
+
+impl PlacementResult {
+    /// Whether the file is at `target` now or was there already. Not so if
+    /// it was skipped, failed or its copy did not match and was removed.
+    pub fn landed(&self) -> bool {
+        !matches!(
+            self.outcome,
+            PlacementOutcome::Skipped | PlacementOutcome::Failed
+        ) && self.verification != Some(Verification::Mismatch)
+    }
+
+    /// A file that could not be placed because of `error`.
+    pub fn failed(source: &Path, error: &dyn std::fmt::Display) -> PlacementResult {
+        PlacementResult {
+            source: source.display().to_string(),
+            target: source.display().to_string(),
+            outcome: PlacementOutcome::Failed,
+            verification: None,
+            message: Some(error.to_string()),
+            hash: None,
+        }
+    }
+
+    /// A member of a group whose primary file did not land. It stays where it
+    /// is, since next to the file at the target it would belong to another photo.
+    pub fn member_left(member: &Path) -> PlacementResult {
+        PlacementResult {
+            source: member.display().to_string(),
+            target: member.display().to_string(),
+            outcome: PlacementOutcome::Skipped,
+            verification: None,
+            message: None,
+            hash: None,
+        }
+    }
+}
+
+/// Places `source` at `target`, or next to it if the policy says so. The
+/// parent folder of `target` must exist. With `verify` set, copies are
+/// compared with their source, and a source is only removed once its copy
+/// matched. `on_bytes` is called like in `copy_engine::copy_file`.
+pub fn place(
+    transaction: &Transaction,
+    source: &Path,
+    target: &Path,
+    policy: ConflictPolicy,
+    transfer: Transfer,
+    verify: bool,
+    on_bytes: &dyn Fn(u64),
+) -> io::Result<PlacementResult> {
+    let result = |target: &Path, outcome| PlacementResult {
+        source: source.display().to_string(),
+        target: target.display().to_string(),
+        outcome,
+        verification: None,
+        message: None,
+        hash: None,
+    };
+    let transferred = |target: &Path, outcome, transferred: Transferred| PlacementResult {
+        verification: transferred.verification,
+        hash: transferred.hash,
+        ..result(target, outcome)
+    };
+    let placed = |target: &Path, outcome| -> io::Result<PlacementResult> {
+        let done = run(transaction, source, target, transfer, verify, on_bytes)?;
+        Ok(transferred(target, outcome, done))
+    };
+    if !exists(target)? {
+        match placed(target, PlacementOutcome::Placed) {
+            // Taken since it was checked, so it is a conflict after all
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            result => return result,
+        }
+    }
+    if same_file(source, target)? {
+        // Sorting a file that is already in place
+        return Ok(result(target, PlacementOutcome::Identical));
+    }
+
+    match policy {
+        ConflictPolicy::Skip => Ok(result(target, PlacementOutcome::Skipped)),
+        ConflictPolicy::Overwrite => {
+            if same_content(source, target)? {
+                return Ok(result(target, PlacementOutcome::Identical));
+            }
+            let done = replace(transaction, source, target, transfer, verify, on_bytes)?;
+            Ok(transferred(target, PlacementOutcome::Overwritten, done))
+        }
+        ConflictPolicy::Rename | ConflictPolicy::KeepBoth => {
+            // An identical file under one of the free names is from an earlier
+            // import, so importing again does not pile up copies
+            let mut n = 0;
+            loop {
+                let candidate = match n {
+                    0 => target.to_path_buf(),
+                    n => numbered(target, n),
+                };
+                if !exists(&candidate)? {
+                    match placed(&candidate, PlacementOutcome::Renamed) {
+                        // Taken since it was checked; looked at like any other file
+                        Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                        result => return result,
+                    }
+                }
+                if policy == ConflictPolicy::Rename && same_content(source, &candidate)? {
+                    return Ok(result(&candidate, PlacementOutcome::Identical));
+                }
+                n += 1;
+            }
+        }
+    }
+}
+
+/// Places the primary file of a group at `target` and its other files next
+/// to wherever the primary file ended up, under the same new stem. If the
+/// primary file did not land, the others are skipped as well. A file that
+/// cannot be placed is reported as `Failed`; there is a result for every file.
+pub fn place_group(
+    transaction: &Transaction,
+    group: &FileGroup,
+    target: &Path,
+    policy: ConflictPolicy,
+    transfer: Transfer,
+    verify: bool,
+    on_bytes: &dyn Fn(u64),
+) -> Vec<PlacementResult> {
+    let primary = place(
+        transaction,
+        &group.primary,
+        target,
+        policy,
+        transfer,
+        verify,
+        on_bytes,
+    )
+    .unwrap_or_else(|e| PlacementResult::failed(&group.primary, &e));
+    let primary_target = PathBuf::from(&primary.target);
+    let landed = primary.landed();
+    let mut results = vec![primary];
+    for member in &group.members {
+        if !landed {
+            results.push(PlacementResult::member_left(member));
+            continue;
+        }
+        let target = group.member_target(member, &primary_target);
+        results.push(
+            place(
+                transaction,
+                member,
+                &target,
+                policy,
+                transfer,
+                verify,
+                on_bytes,
+            )
+            .unwrap_or_else(|e| PlacementResult::failed(member, &e)),
+        );
+    }
+    results
+}
+
+fn run(
+    transaction: &Transaction,
+    source: &Path,
+    target: &Path,
+    transfer: Transfer,
+    verify: bool,
+    on_bytes: &dyn Fn(u64),
+) -> io::Result<Transferred> {
+    let unhashed = Transferred {
+        verification: None,
+        hash: None,
+    };
+    match (transfer, verify) {
+        (Transfer::Rename, _) => transaction.rename(source, target).map(|_| unhashed),
+        (Transfer::Copy, false) => transaction.copy(source, target, on_bytes).map(|_| unhashed),
+        (Transfer::CopyAndRemove, false) => transaction
+            .copy_and_remove(source, target, on_bytes)
+            .map(|_| unhashed),
+        (_, true) => {
+            let (verification, hash) = copy_verified(transaction, source, target, on_bytes)?;
+            if verification == Verification::Mismatch {
+                // Neither recorded nor kept, so the corrupt copy cannot be mistaken for the photo
+                fs::remove_file(target)?;
+            } else if transfer == Transfer::CopyAndRemove {
+                transaction.remove_copied(source, target)?;
+            } else {
+                transaction.record(Operation::Copy {
+                    from: source.to_path_buf(),
+                    to: target.to_path_buf(),
+                })?;
+            }
+            Ok(Transferred {
+                verification: Some(verification),
+                hash: Some(hash),
+            })
+        }
+    }
+}
+
+/// Replaces the file at `target` so that neither file is ever lost: the new
+/// file is copied next to it first and only moved into place once complete
+/// and, with `verify` set, matching. The replaced file goes to the trash and
+/// is recorded, so an undo brings it back.
+fn replace(
+    transaction: &Transaction,
+    source: &Path,
+    target: &Path,
+    transfer: Transfer,
+    verify: bool,
+    on_bytes: &dyn Fn(u64),
+) -> io::Result<Transferred> {
+    if transfer == Transfer::Rename {
+        // A rename either happens completely or not at all, so it needs no temporary file
+        transaction.record(move_to_trash(target)?)?;
+        return run(transaction, source, target, transfer, verify, on_bytes);
+    }
+
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = target.with_file_name(format!(".{}.overwrite-tmp", name));
+    // Left over from an overwrite that was interrupted
+    let _ = fs::remove_file(&temp);
+    let done = if verify {
+        let (verification, hash) = copy_verified(transaction, source, &temp, on_bytes)
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })?;
+        if verification == Verification::Mismatch {
+            // The existing file stays untouched
+            fs::remove_file(&temp)?;
+        }
+        Transferred {
+            verification: Some(verification),
+            hash: Some(hash),
+        }
+    } else {
+        transaction.copy_unrecorded(source, &temp, on_bytes)?;
+        Transferred {
+            verification: None,
+            hash: None,
+        }
+    };
+    if done.verification == Some(Verification::Mismatch) {
+        return Ok(done);
+    }
+    move_to_trash(target)
+        .and_then(|trashed| transaction.record(trashed))
+        .and_then(|_| copy_engine::rename_file(&temp, target))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp);
+        })?;
+    if transfer == Transfer::CopyAndRemove {
+        transaction.remove_copied(source, target)?;
+    } else {
+        transaction.record(Operation::Copy {
+            from: source.to_path_buf(),
+            to: target.to_path_buf(),
+        })?;
+    }
+    Ok(done)
+}
+
+/// Copies and compares the BLAKE3 hashes of source and copy, copying again if
+/// they differ. Flaky card readers and hubs do produce corrupt copies, so the
+/// source is hashed by a read of its own rather than from the bytes that were
+/// copied. The last copy is left for the caller, whatever it turned out like,
+/// together with the hash of the source.
+fn copy_verified(
+    transaction: &Transaction,
+    source: &Path,
+    target: &Path,
+    on_bytes: &dyn Fn(u64),
+) -> io::Result<(Verification, String)> {
+    let expected = full_hash(source)?;
+    for attempt in 0..=VERIFY_RETRIES {
+        if attempt > 0 {
+            // The copy is never replaced in place, so the corrupt one goes first
+            fs::remove_file(target)?;
+        }
+        transaction.copy_unrecorded(source, target, on_bytes)?;
+        if full_hash(target)? == expected {
+            let verification = if attempt == 0 {
+                Verification::Verified
+            } else {
+                Verification::Repaired
+            };
+            return Ok((verification, expected));
+        }
+    }
+    Ok((Verification::Mismatch, expected))
+}
+
+/// Unlike `Path::exists`, a broken symlink counts and errors are not swallowed.
+fn exists(path: &Path) -> io::Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> io::Result<bool> {
+    Ok(fs::canonicalize(a)? == fs::canonicalize(b)?)
+}
+
+/// `IMG_0001.JPG` becomes `IMG_0001_1.JPG`.
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, n),
+    };
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members_stay_behind_when_the_primary_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        let library = dir.path().join("library");
+        fs::create_dir_all(&card).unwrap();
+        fs::create_dir_all(&library).unwrap();
+        fs::write(card.join("IMG_0001.CR2"), "new raw").unwrap();
+        fs::write(card.join("IMG_0001.xmp"), "new sidecar").unwrap();
+        fs::write(library.join("IMG_0001.CR2"), "another photo").unwrap();
+
+        let group = FileGroup {
+            primary: card.join("IMG_0001.CR2"),
+            members: vec![card.join("IMG_0001.xmp")],
+        };
+        let transaction = Transaction::begin("test", "");
+        let results = place_group(
+            &transaction,
+            &group,
+            &library.join("IMG_0001.CR2"),
+            ConflictPolicy::Skip,
+            Transfer::Copy,
+            false,
+            &|_| {},
+        );
+
+        let outcomes: Vec<_> = results.iter().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, [PlacementOutcome::Skipped; 2]);
+        assert_eq!(
+            results[1].target,
+            card.join("IMG_0001.xmp").display().to_string()
+        );
+        assert!(!library.join("IMG_0001.xmp").exists());
+    }
+
+    #[test]
+    fn every_file_of_a_failed_group_gets_a_result() {
+        let dir = tempfile::tempdir().unwrap();
+        let card = dir.path().join("card");
+        fs::create_dir_all(&card).unwrap();
+        fs::write(card.join("IMG_0002.xmp"), "sidecar").unwrap();
+
+        // The photo vanished since the card was read
+        let group = FileGroup {
+            primary: card.join("IMG_0002.JPG"),
+            members: vec![card.join("IMG_0002.xmp")],
+        };
+        let transaction = Transaction::begin("test", "");
+        let results = place_group(
+            &transaction,
+            &group,
+            &dir.path().join("IMG_0002.JPG"),
+            ConflictPolicy::Rename,
+            Transfer::Rename,
+            false,
+            &|_| {},
+        );
+
+        let outcomes: Vec<_> = results.iter().map(|r| r.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [PlacementOutcome::Failed, PlacementOutcome::Skipped]
+        );
+        assert!(results[0].message.is_some());
+        assert!(card.join("IMG_0002.xmp").exists());
+    }
+
+    #[test]
+    fn numbered_names_keep_the_extension() {
+        let path = Path::new("/photos/IMG_0001.JPG");
+        assert_eq!(numbered(path, 2), Path::new("/photos/IMG_0001_2.JPG"));
+        assert_eq!(
+            numbered(Path::new("/photos/README"), 1),
+            Path::new("/photos/README_1")
+        );
+    }
+}
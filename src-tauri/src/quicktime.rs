@@ -0,0 +1,135 @@
+//! Just enough of an ISO base media (MP4/QuickTime) reader to find out when a
+//! clip was recorded.
+
+use chrono::{DateTime, TimeDelta, Utc};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Seconds from 1904-01-01, the epoch of QuickTime times, to 1970-01-01.
+const SECONDS_1904_TO_1970: i64 = 2_082_844_800;
+
+/// The creation time in the movie header (`moov/mvhd`). Cameras often write
+/// the `moov` box after the media data, so boxes are skipped by seeking
+/// rather than read. Unset times are zero and yield `None`.
+pub fn creation_time(path: &Path) -> Option<DateTime<Utc>> {
+    let mut file = File::open(path).ok()?;
+    let file_len = file.metadata().ok()?.len();
+    let (moov_start, moov_end) = find_box(&mut file, 0, file_len, b"moov")?;
+    let (mvhd_start, _) = find_box(&mut file, moov_start, moov_end, b"mvhd")?;
+
+    file.seek(SeekFrom::Start(mvhd_start)).ok()?;
+    let mut version = [0u8; 4];
+    file.read_exact(&mut version).ok()?;
+    let seconds = if version[0] == 1 {
+        let mut bytes = [0u8; 8];
+        file.read_exact(&mut bytes).ok()?;
+        u64::from_be_bytes(bytes)
+    } else {
+        let mut bytes = [0u8; 4];
+        file.read_exact(&mut bytes).ok()?;
+        u32::from_be_bytes(bytes) as u64
+    };
+    if seconds == 0 {
+        return None;
+    }
+    let since_1970 = i64::try_from(seconds).ok()? - SECONDS_1904_TO_1970;
+    DateTime::UNIX_EPOCH.checked_add_signed(TimeDelta::try_seconds(since_1970)?)
+}
+
+/// Looks for a box of type `name` among the boxes between `start` and `end`
+/// and returns where its content starts and ends.
+fn find_box(file: &mut File, start: u64, end: u64, name: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut at = start;
+    while at + 8 <= end {
+        file.seek(SeekFrom::Start(at)).ok()?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+        let size = u32::from_be_bytes(header[..4].try_into().ok()?) as u64;
+        let (header_len, size) = match size {
+            // The box extends to the end of the file
+            0 => (8, end - at),
+            // The size follows the type as a 64-bit value
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).ok()?;
+                (16, u64::from_be_bytes(large))
+            }
+            size => (8, size),
+        };
+        if size < header_len {
+            return None;
+        }
+        if &header[4..] == name {
+            return Some((at + header_len, (at + size).min(end)));
+        }
+        at = at.checked_add(size)?;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn boxed(name: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut bytes = (8 + content.len() as u32).to_be_bytes().to_vec();
+        bytes.extend(name);
+        bytes.extend(content);
+        bytes
+    }
+
+    /// A clip with the media data first and a movie header of `version`.
+    fn clip(version: u8, seconds: u64) -> tempfile::NamedTempFile {
+        let mut mvhd = vec![version, 0, 0, 0];
+        match version {
+            1 => mvhd.extend(seconds.to_be_bytes()),
+            _ => mvhd.extend((seconds as u32).to_be_bytes()),
+        }
+        mvhd.extend([0u8; 16]);
+        // Media data with a 64-bit size, as large clips have
+        let mut mdat = 1u32.to_be_bytes().to_vec();
+        mdat.extend(b"mdat");
+        mdat.extend(20u64.to_be_bytes());
+        mdat.extend([7u8; 4]);
+
+        let mut bytes = boxed(b"ftyp", b"qt  \0\0\0\0");
+        bytes.extend(mdat);
+        let mut moov = boxed(b"trak", &[0u8; 12]);
+        moov.extend(boxed(b"mvhd", &mvhd));
+        bytes.extend(boxed(b"moov", &moov));
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), bytes).unwrap();
+        file
+    }
+
+    fn quicktime_seconds(time: DateTime<Utc>) -> u64 {
+        (time.timestamp() + SECONDS_1904_TO_1970) as u64
+    }
+
+    #[test]
+    fn reads_32_bit_times_since_1904() {
+        let recorded = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        assert_eq!(quicktime_seconds(recorded), 3_786_955_200);
+        let file = clip(0, quicktime_seconds(recorded));
+        assert_eq!(creation_time(file.path()), Some(recorded));
+    }
+
+    #[test]
+    fn reads_64_bit_times() {
+        // Beyond what 32 bits hold since 1904
+        let recorded = Utc.with_ymd_and_hms(2050, 6, 1, 8, 30, 0).unwrap();
+        assert!(quicktime_seconds(recorded) > u32::MAX as u64);
+        let file = clip(1, quicktime_seconds(recorded));
+        assert_eq!(creation_time(file.path()), Some(recorded));
+    }
+
+    #[test]
+    fn unset_times_and_other_files_have_none() {
+        assert_eq!(creation_time(clip(0, 0).path()), None);
+        let photo = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(photo.path(), b"\xff\xd8\xff\xe0 no clip").unwrap();
+        assert_eq!(creation_time(photo.path()), None);
+    }
+}
@@ -0,0 +1,142 @@
+//! Finds the JPEG previews cameras embed in their RAW files. The `image` crate
+//! cannot decode the sensor data, but the previews are plain JPEGs, most of
+//! them in full size.
+
+use crate::exif::Reader;
+
+const STRIP_OFFSETS: u16 = 0x0111;
+const ORIENTATION: u16 = 0x0112;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014a;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+/// TIFF field types.
+const SHORT: u16 = 3;
+const UNDEFINED: u16 = 7;
+
+/// IFDs followed at most, so a broken file cannot send the walk in circles.
+const MAX_IFDS: usize = 64;
+
+/// Bytes of a CR3 file searched for its preview box.
+const CR3_HEADER_BYTES: usize = 4 * 1024 * 1024;
+
+pub struct RawPreviews<'a> {
+    /// The embedded JPEGs, largest first
+    pub jpegs: Vec<&'a [u8]>,
+    /// The EXIF orientation of the RAW file, which the previews often lack
+    pub orientation: Option<u8>,
+}
+
+/// Looks for previews in a RAW file: in the IFDs of TIFF-based formats (CR2,
+/// NEF, ARW, DNG, RW2, ...), in the `PRVW` box of CR3 and behind the header
+/// of RAF. `None` for other files.
+pub fn previews(data: &[u8]) -> Option<RawPreviews<'_>> {
+    let (mut jpegs, orientation) = if data.starts_with(b"FUJIFILMCCD-RAW ") {
+        (raf_preview(data).into_iter().collect(), None)
+    } else if data.get(4..12) == Some(b"ftypcrx ") {
+        (cr3_preview(data).into_iter().collect(), None)
+    } else {
+        tiff_previews(data)?
+    };
+    jpegs.sort_by_key(|jpeg: &&[u8]| std::cmp::Reverse(jpeg.len()));
+    Some(RawPreviews { jpegs, orientation })
+}
+
+/// Walks IFD0, the IFDs chained to it and their SubIFDs. Previews are stored
+/// as JPEG interchange format, as a single strip of JPEG data, or, like the
+/// full-size preview of RW2, as a JPEG blob in an undefined tag.
+fn tiff_previews(data: &[u8]) -> Option<(Vec<&[u8]>, Option<u8>)> {
+    let reader = Reader::new(data)?;
+    let jpeg_at = |offset: u32, len: u32| {
+        let start = offset as usize;
+        let jpeg = data.get(start..start.checked_add(len as usize)?)?;
+        jpeg.starts_with(&[0xff, 0xd8]).then_some(jpeg)
+    };
+
+    let mut jpegs = Vec::new();
+    let mut orientation = None;
+    let mut pending = vec![reader.u32(4)?];
+    let mut visited = Vec::new();
+    while let Some(ifd) = pending.pop() {
+        if ifd == 0 || visited.contains(&ifd) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(ifd);
+        let offset = ifd as usize;
+        let (mut interchange, mut interchange_len) = (None, None);
+        let (mut strip, mut strip_len) = (None, None);
+        let mut entries = 0;
+        for at in reader.entries(offset) {
+            entries += 1;
+            let (Some(tag), Some(kind), Some(count)) =
+                (reader.u16(at), reader.u16(at + 2), reader.u32(at + 4))
+            else {
+                continue;
+            };
+            // Values of a single SHORT sit in the first half of the value field
+            let value = if kind == SHORT {
+                reader.u16(at + 8).map(u32::from)
+            } else {
+                reader.u32(at + 8)
+            };
+            match tag {
+                JPEG_INTERCHANGE_FORMAT => interchange = value,
+                JPEG_INTERCHANGE_FORMAT_LENGTH => interchange_len = value,
+                // Several strips cannot be joined into one JPEG
+                STRIP_OFFSETS if count == 1 => strip = value,
+                STRIP_BYTE_COUNTS if count == 1 => strip_len = value,
+                ORIENTATION if visited.len() == 1 => {
+                    orientation = value.and_then(|v| u8::try_from(v).ok())
+                }
+                SUB_IFDS => {
+                    if count == 1 {
+                        pending.extend(value);
+                    } else if let Some(list) = reader.u32(at + 8) {
+                        pending.extend(
+                            (0..count.min(MAX_IFDS as u32))
+                                .filter_map(|i| reader.u32(list as usize + 4 * i as usize)),
+                        );
+                    }
+                }
+                _ if kind == UNDEFINED && count > 4 => {
+                    jpegs.extend(value.and_then(|v| jpeg_at(v, count)));
+                }
+                _ => {}
+            }
+        }
+        for (start, len) in [(interchange, interchange_len), (strip, strip_len)] {
+            if let (Some(start), Some(len)) = (start, len) {
+                jpegs.extend(jpeg_at(start, len));
+            }
+        }
+        pending.extend(reader.u32(offset + 2 + 12 * entries));
+    }
+    Some((jpegs, orientation))
+}
+
+/// The header names offset and length of the JPEG at 84 and 88.
+fn raf_preview(data: &[u8]) -> Option<&[u8]> {
+    let be = |at: usize| -> Option<usize> {
+        let bytes: [u8; 4] = data.get(at..at + 4)?.try_into().ok()?;
+        Some(u32::from_be_bytes(bytes) as usize)
+    };
+    let (start, len) = (be(84)?, be(88)?);
+    let jpeg = data.get(start..start.checked_add(len)?)?;
+    jpeg.starts_with(&[0xff, 0xd8]).then_some(jpeg)
+}
+
+/// The `PRVW` box holds a 1620 pixel wide JPEG, its length in the four bytes
+/// before the JPEG.
+fn cr3_preview(data: &[u8]) -> Option<&[u8]> {
+    let header = &data[..data.len().min(CR3_HEADER_BYTES)];
+    let prvw = header.windows(4).position(|w| w == b"PRVW")?;
+    let soi = prvw
+        + header
+            .get(prvw..prvw + 32)?
+            .windows(3)
+            .position(|w| w == [0xff, 0xd8, 0xff])?;
+    let bytes: [u8; 4] = data.get(soi - 4..soi)?.try_into().ok()?;
+    let len = u32::from_be_bytes(bytes) as usize;
+    data.get(soi..soi.checked_add(len)?)
+}
@@ -2,12 +2,21 @@ mod scan_images;
 mod find_images;
 mod sort_images;
 
+use crate::placement::{ConflictPolicy, PlacementResult};
+
 #[tauri::command]
 pub fn find_images(path: String) -> Result<Vec<String>, String> {
     find_images::find_images(path)
 }
 
+/// Moves the images below `path` to where `template` puts them, by default
+/// into folders per year and day. Reports what was done with every image;
+/// `conflict` decides about images whose target is taken by a different file.
 #[tauri::command]
-pub fn sort_images(path: String) -> Result<(), String> {
-    sort_images::sort_images(path)
+pub fn sort_images(
+    path: String,
+    template: Option<String>,
+    conflict: Option<ConflictPolicy>,
+) -> Result<Vec<PlacementResult>, String> {
+    sort_images::sort_images(path, template, conflict.unwrap_or_default())
 }
@@ -1,26 +1,44 @@
-use std::{fs, path::PathBuf};
-use chrono::prelude::*;
+use std::path::PathBuf;
 
 use super::scan_images::scan_images;
+use crate::file_groups::group_files;
+use crate::journal::Transaction;
+use crate::placement::{self, ConflictPolicy, PlacementResult, Transfer};
+use crate::template::{PathTemplate, Sequence};
 
-pub fn sort_images(path: String) -> Result<(), String> {
+pub fn sort_images(
+    path: String,
+    template: Option<String>,
+    conflict: ConflictPolicy,
+) -> Result<Vec<PlacementResult>, String> {
+    let template = PathTemplate::or_default(template.as_deref())?;
     let root = PathBuf::from(&path);
-    let files = scan_images(&root);
-    for file in files {
-        let metadata = file.metadata().map_err(|e| e.to_string())?;
-        let mtime = metadata.modified().map_err(|e| e.to_string())?;
-        let dt: DateTime<Local> = mtime.into();
-        let year = dt.format("%Y").to_string();
-        let month = dt.format("%m").to_string();
-        let dest_dir = root.join(year).join(month);
-        fs::create_dir_all(&dest_dir).map_err(|e| e.to_string())?;
-        let target = dest_dir.join(
-            file.file_name()
-                .ok_or_else(|| "Invalid file name".to_string())?,
-        );
-        if target != file {
-            fs::rename(&file, &target).map_err(|e| e.to_string())?;
+    let groups = group_files(scan_images(&root));
+    let transaction = Transaction::begin("sort_images", &path);
+    let mut sequence = Sequence::default();
+    let mut results = Vec::new();
+    for group in groups {
+        let target = template
+            .render(&group.primary, &mut sequence)
+            .map(|target| root.join(target))
+            .and_then(|target| {
+                if let Some(dest_dir) = target.parent() {
+                    transaction.create_dir_all(dest_dir)?;
+                }
+                Ok(target)
+            });
+        match target {
+            Ok(target) => results.extend(placement::place_group(
+                &transaction,
+                &group,
+                &target,
+                conflict,
+                Transfer::Rename,
+                false,
+                &|_| {},
+            )),
+            Err(e) => results.extend(group.files().map(|file| PlacementResult::failed(file, &e))),
         }
     }
-    Ok(())
+    Ok(results)
 }
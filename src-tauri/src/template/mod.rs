@@ -0,0 +1,25 @@
+mod path_template;
+mod preview_template;
+
+pub use path_template::{PathTemplate, Sequence, DEFAULT_TEMPLATE};
+pub use preview_template::TemplatePreview;
+
+/// Checks a folder and file name template; the error says what is wrong with it.
+#[tauri::command]
+pub fn validate_template(template: String) -> Result<(), String> {
+    PathTemplate::parse(&template).map(|_| ())
+}
+
+/// Shows where `files` would be put by `template`, or some sample photos if
+/// no files are given.
+#[tauri::command]
+pub async fn preview_template(
+    template: String,
+    files: Option<Vec<String>>,
+) -> Result<Vec<TemplatePreview>, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        preview_template::preview_template(&template, &files.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
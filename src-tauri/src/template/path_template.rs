@@ -0,0 +1,433 @@
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::capture_date;
+use crate::duplicate::full_hash;
+use crate::exif;
+
+/// The layout used when no template is given: one folder per year and day,
+/// with the original file name.
+pub const DEFAULT_TEMPLATE: &str = "{year}/{year}-{month}-{day}/{original_name}.{ext}";
+
+/// Stands in for camera and lens if a photo does not name them.
+const UNKNOWN: &str = "unknown";
+
+/// Characters that are not allowed in file names on at least one of the
+/// supported systems.
+const FORBIDDEN: [char; 7] = ['<', '>', ':', '"', '|', '?', '*'];
+
+const MAX_SEQ_WIDTH: usize = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    CameraModel,
+    Lens,
+    Ext,
+    OriginalName,
+    /// Counts the files of a folder, zero-padded to the given width
+    Seq(usize),
+    /// The first eight hex digits of the BLAKE3 hash of the content
+    Hash8,
+}
+
+impl Token {
+    fn parse(name: &str) -> Result<Token, String> {
+        Ok(match name {
+            "year" => Token::Year,
+            "month" => Token::Month,
+            "day" => Token::Day,
+            "hour" => Token::Hour,
+            "minute" => Token::Minute,
+            "second" => Token::Second,
+            "camera_model" => Token::CameraModel,
+            "lens" => Token::Lens,
+            "ext" => Token::Ext,
+            "original_name" => Token::OriginalName,
+            "hash8" => Token::Hash8,
+            "seq" => Token::Seq(1),
+            _ => match name.strip_prefix("seq:") {
+                Some(width) => match width.parse() {
+                    Ok(width) if (1..=MAX_SEQ_WIDTH).contains(&width) => Token::Seq(width),
+                    _ => {
+                        return Err(format!(
+                            "the width of {{{}}} must be a number from 1 to {}",
+                            name, MAX_SEQ_WIDTH
+                        ))
+                    }
+                },
+                None => return Err(format!("unknown token {{{}}}", name)),
+            },
+        })
+    }
+
+    fn is_date(self) -> bool {
+        matches!(
+            self,
+            Token::Year | Token::Month | Token::Day | Token::Hour | Token::Minute | Token::Second
+        )
+    }
+
+    /// Whether the token tells files of the same folder apart.
+    fn is_distinct(self) -> bool {
+        matches!(self, Token::OriginalName | Token::Seq(_) | Token::Hash8)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Token(Token),
+}
+
+/// A validated template for the path of a file below the destination, e.g.
+/// `{year}/{month}/{camera_model}_{seq:4}.{ext}`. The last segment is the
+/// file name, all others are folders.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    segments: Vec<Vec<Part>>,
+}
+
+/// What a template can say about a file. Only the values the template uses
+/// are read.
+#[derive(Debug, Clone, Default)]
+pub struct FileFacts {
+    pub original_name: String,
+    pub ext: String,
+    pub taken: Option<NaiveDateTime>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    pub hash8: Option<String>,
+}
+
+/// Numbers handed out by `{seq}` so far, per target folder. One instance is
+/// shared by all files of a run.
+#[derive(Debug, Default)]
+pub struct Sequence(HashMap<PathBuf, u64>);
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<PathTemplate, String> {
+        let template = template.trim();
+        if template.is_empty() {
+            return Err("the template is empty".to_string());
+        }
+        if template.starts_with(['/', '\\']) {
+            return Err("the template must be relative to the destination".to_string());
+        }
+        let segments = template
+            .split(['/', '\\'])
+            .map(parse_segment)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (file_name, folders) = segments.split_last().expect("split yields a segment");
+        let tokens = |parts: &[Part]| -> Vec<Token> {
+            parts
+                .iter()
+                .filter_map(|part| match part {
+                    Part::Token(token) => Some(*token),
+                    Part::Text(_) => None,
+                })
+                .collect()
+        };
+        if folders
+            .iter()
+            .any(|parts| tokens(parts).iter().any(|t| matches!(t, Token::Seq(_))))
+        {
+            return Err("{seq} can only be used in the file name".to_string());
+        }
+        let file_tokens = tokens(file_name);
+        if !file_tokens.contains(&Token::Ext) {
+            return Err("the file name must contain {ext}".to_string());
+        }
+        if !file_tokens.iter().any(|t| t.is_distinct()) {
+            return Err(
+                "the file name must contain {original_name}, {seq} or {hash8}, otherwise all files of a folder get the same name"
+                    .to_string(),
+            );
+        }
+        Ok(PathTemplate { segments })
+    }
+
+    /// Parses `template`, or the default template if there is none.
+    pub fn or_default(template: Option<&str>) -> Result<PathTemplate, String> {
+        PathTemplate::parse(template.unwrap_or(DEFAULT_TEMPLATE))
+    }
+
+    fn uses(&self, matches: impl Fn(Token) -> bool) -> bool {
+        self.segments.iter().flatten().any(|part| match part {
+            Part::Token(token) => matches(*token),
+            Part::Text(_) => false,
+        })
+    }
+
+    /// Reads what the template needs to know about a file.
+    pub fn facts(&self, path: &Path) -> io::Result<FileFacts> {
+        let text = |s: Option<&std::ffi::OsStr>| s.map(|s| s.to_string_lossy().into_owned());
+        let mut facts = FileFacts {
+            original_name: text(path.file_stem()).unwrap_or_default(),
+            ext: text(path.extension()).unwrap_or_default(),
+            ..FileFacts::default()
+        };
+        if self.uses(Token::is_date) {
+            facts.taken = Some(capture_date::read(path)?.local);
+        }
+        if self.uses(|t| t == Token::CameraModel || t == Token::Lens) {
+            if let Some(data) = exif::read(path) {
+                facts.camera_model = data.string(exif::MODEL).map(str::to_string);
+                facts.lens = data.string(exif::LENS_MODEL).map(str::to_string);
+            }
+        }
+        if self.uses(|t| t == Token::Hash8) {
+            facts.hash8 = Some(full_hash(path)?[..8].to_string());
+        }
+        Ok(facts)
+    }
+
+    /// The path of `path` below the destination.
+    pub fn render(&self, path: &Path, sequence: &mut Sequence) -> io::Result<PathBuf> {
+        Ok(self.render_facts(&self.facts(path)?, sequence))
+    }
+
+    pub fn render_facts(&self, facts: &FileFacts, sequence: &mut Sequence) -> PathBuf {
+        let (file_name, folders) = self.segments.split_last().expect("parsed with a segment");
+        let folder: PathBuf = folders
+            .iter()
+            .map(|parts| render_segment(parts, facts, 0))
+            .collect();
+        let number = if self.uses(|t| matches!(t, Token::Seq(_))) {
+            let counter = sequence.0.entry(folder.clone()).or_insert(0);
+            *counter += 1;
+            *counter
+        } else {
+            0
+        };
+        folder.join(render_segment(file_name, facts, number))
+    }
+}
+
+fn parse_segment(segment: &str) -> Result<Vec<Part>, String> {
+    if segment.trim().is_empty() {
+        return Err("the template contains an empty folder name".to_string());
+    }
+    if segment == "." || segment == ".." {
+        return Err(format!("'{}' is not allowed as a folder name", segment));
+    }
+    let mut parts = Vec::new();
+    let mut rest = segment;
+    while !rest.is_empty() {
+        let text_end = rest.find(['{', '}']).unwrap_or(rest.len());
+        let text = &rest[..text_end];
+        if let Some(c) = text
+            .chars()
+            .find(|c| FORBIDDEN.contains(c) || c.is_control())
+        {
+            return Err(format!("'{}' is not allowed in file names", c));
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text.to_string()));
+        }
+        rest = &rest[text_end..];
+        if rest.starts_with('}') {
+            return Err("'}' without a matching '{'".to_string());
+        }
+        if let Some(token) = rest.strip_prefix('{') {
+            let end = token
+                .find('}')
+                .ok_or_else(|| "'{' without a matching '}'".to_string())?;
+            parts.push(Part::Token(Token::parse(&token[..end])?));
+            rest = &token[end + 1..];
+        }
+    }
+    Ok(parts)
+}
+
+fn render_segment(parts: &[Part], facts: &FileFacts, number: u64) -> String {
+    let date = |value: fn(&NaiveDateTime) -> u32, width: usize| {
+        facts
+            .taken
+            .as_ref()
+            .map(|taken| format!("{:0width$}", value(taken), width = width))
+            .unwrap_or_else(|| UNKNOWN.to_string())
+    };
+    let mut segment = String::new();
+    for part in parts {
+        let value = match part {
+            Part::Text(text) => {
+                segment.push_str(text);
+                continue;
+            }
+            Part::Token(Token::Year) => date(|t| t.year() as u32, 4),
+            Part::Token(Token::Month) => date(NaiveDateTime::month, 2),
+            Part::Token(Token::Day) => date(NaiveDateTime::day, 2),
+            Part::Token(Token::Hour) => date(NaiveDateTime::hour, 2),
+            Part::Token(Token::Minute) => date(NaiveDateTime::minute, 2),
+            Part::Token(Token::Second) => date(NaiveDateTime::second, 2),
+            Part::Token(Token::CameraModel) => {
+                facts.camera_model.as_deref().unwrap_or(UNKNOWN).to_string()
+            }
+            Part::Token(Token::Lens) => facts.lens.as_deref().unwrap_or(UNKNOWN).to_string(),
+            Part::Token(Token::Ext) => facts.ext.clone(),
+            Part::Token(Token::OriginalName) => facts.original_name.clone(),
+            Part::Token(Token::Seq(width)) => format!("{:0width$}", number, width = width),
+            Part::Token(Token::Hash8) => facts.hash8.as_deref().unwrap_or(UNKNOWN).to_string(),
+        };
+        segment.extend(value.chars().map(|c| {
+            if FORBIDDEN.contains(&c) || c == '/' || c == '\\' || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        }));
+    }
+    // Windows drops trailing dots and spaces, e.g. of `name.{ext}` without extension
+    let trimmed = segment.trim_end_matches(['.', ' ']);
+    if trimmed.is_empty() {
+        UNKNOWN.to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn facts() -> FileFacts {
+        FileFacts {
+            original_name: "IMG_0001".to_string(),
+            ext: "JPG".to_string(),
+            taken: NaiveDate::from_ymd_opt(2024, 3, 7).and_then(|d| d.and_hms_opt(9, 5, 2)),
+            camera_model: Some("X100V".to_string()),
+            lens: None,
+            hash8: Some("0123abcd".to_string()),
+        }
+    }
+
+    fn render(template: &str, facts: &FileFacts) -> String {
+        PathTemplate::parse(template)
+            .unwrap()
+            .render_facts(facts, &mut Sequence::default())
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+
+    #[test]
+    fn renders_every_token() {
+        assert_eq!(
+            render(
+                "{year}/{month}-{day}/{hour}{minute}{second}_{camera_model}_{lens}_{hash8}_{original_name}.{ext}",
+                &facts()
+            ),
+            "2024/03-07/090502_X100V_unknown_0123abcd_IMG_0001.JPG"
+        );
+        let undated = FileFacts {
+            taken: None,
+            ..facts()
+        };
+        assert_eq!(
+            render(DEFAULT_TEMPLATE, &undated),
+            "unknown/unknown-unknown-unknown/IMG_0001.JPG"
+        );
+    }
+
+    #[test]
+    fn rejects_broken_templates() {
+        for (template, error) in [
+            ("", "the template is empty"),
+            (
+                "/{original_name}.{ext}",
+                "the template must be relative to the destination",
+            ),
+            (
+                "{year}//{original_name}.{ext}",
+                "the template contains an empty folder name",
+            ),
+            (
+                "../{original_name}.{ext}",
+                "'..' is not allowed as a folder name",
+            ),
+            ("{yaer}/{original_name}.{ext}", "unknown token {yaer}"),
+            ("{year/{original_name}.{ext}", "'{' without a matching '}'"),
+            ("year}/{original_name}.{ext}", "'}' without a matching '{'"),
+            (
+                "a:b/{original_name}.{ext}",
+                "':' is not allowed in file names",
+            ),
+            (
+                "{seq}/{original_name}.{ext}",
+                "{seq} can only be used in the file name",
+            ),
+            ("{original_name}", "the file name must contain {ext}"),
+        ] {
+            assert_eq!(
+                PathTemplate::parse(template).unwrap_err(),
+                error,
+                "{}",
+                template
+            );
+        }
+        assert!(PathTemplate::parse("{year}.{ext}")
+            .unwrap_err()
+            .starts_with("the file name must contain {original_name}"));
+    }
+
+    #[test]
+    fn seq_counts_per_folder_with_its_width() {
+        for width in ["0", "10", "x"] {
+            assert_eq!(
+                PathTemplate::parse(&format!("{{seq:{}}}.{{ext}}", width)).unwrap_err(),
+                format!(
+                    "the width of {{seq:{}}} must be a number from 1 to 9",
+                    width
+                )
+            );
+        }
+        let template = PathTemplate::parse("{camera_model}/{seq:4}.{ext}").unwrap();
+        let mut sequence = Sequence::default();
+        let other = FileFacts {
+            camera_model: Some("EOS R5".to_string()),
+            ..facts()
+        };
+        let rendered: Vec<PathBuf> = [facts(), facts(), other]
+            .iter()
+            .map(|facts| template.render_facts(facts, &mut sequence))
+            .collect();
+        assert_eq!(
+            rendered,
+            [
+                Path::new("X100V").join("0001.JPG"),
+                Path::new("X100V").join("0002.JPG"),
+                Path::new("EOS R5").join("0001.JPG"),
+            ]
+        );
+        assert_eq!(render("{seq}.{ext}", &facts()), "1.JPG");
+    }
+
+    #[test]
+    fn values_cannot_break_out_of_their_segment() {
+        let odd = FileFacts {
+            camera_model: Some("A/B:C*D\\E\u{7}".to_string()),
+            ext: String::new(),
+            ..facts()
+        };
+        // The trailing dot of a missing extension is trimmed as well
+        assert_eq!(
+            render("{camera_model}_{original_name}.{ext}", &odd),
+            "A_B_C_D_E__IMG_0001"
+        );
+        let blank = FileFacts {
+            original_name: "...".to_string(),
+            ext: String::new(),
+            ..facts()
+        };
+        assert_eq!(render("{original_name}. {ext}", &blank), "unknown");
+    }
+}
@@ -0,0 +1,95 @@
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::path::Path;
+
+use super::path_template::{FileFacts, PathTemplate, Sequence};
+
+/// Where a file would end up, relative to the destination.
+#[derive(Debug, Clone, Serialize)]
+pub struct TemplatePreview {
+    pub file: String,
+    pub target: Option<String>,
+    /// Why the file could not be read, if it could not
+    pub error: Option<String>,
+}
+
+/// Renders the template for the given files as one run would, so `{seq}`
+/// counts up. Without files, a few made-up photos are used.
+pub fn preview_template(template: &str, files: &[String]) -> Result<Vec<TemplatePreview>, String> {
+    let template = PathTemplate::parse(template)?;
+    let mut sequence = Sequence::default();
+    if files.is_empty() {
+        return Ok(samples()
+            .into_iter()
+            .map(|(file, facts)| TemplatePreview {
+                file: file.to_string(),
+                target: Some(
+                    template
+                        .render_facts(&facts, &mut sequence)
+                        .display()
+                        .to_string(),
+                ),
+                error: None,
+            })
+            .collect());
+    }
+    Ok(files
+        .iter()
+        .map(
+            |file| match template.render(Path::new(file), &mut sequence) {
+                Ok(target) => TemplatePreview {
+                    file: file.clone(),
+                    target: Some(target.display().to_string()),
+                    error: None,
+                },
+                Err(e) => TemplatePreview {
+                    file: file.clone(),
+                    target: None,
+                    error: Some(e.to_string()),
+                },
+            },
+        )
+        .collect())
+}
+
+fn samples() -> Vec<(&'static str, FileFacts)> {
+    let taken = |day, hour, minute, second| {
+        NaiveDate::from_ymd_opt(2024, 7, day)
+            .and_then(|date| date.and_hms_opt(hour, minute, second))
+    };
+    vec![
+        (
+            "IMG_0412.CR3",
+            FileFacts {
+                original_name: "IMG_0412".to_string(),
+                ext: "CR3".to_string(),
+                taken: taken(14, 9, 31, 5),
+                camera_model: Some("Canon EOS R6".to_string()),
+                lens: Some("RF24-105mm F4 L IS USM".to_string()),
+                hash8: Some("3f9a21c0".to_string()),
+            },
+        ),
+        (
+            "IMG_0413.JPG",
+            FileFacts {
+                original_name: "IMG_0413".to_string(),
+                ext: "JPG".to_string(),
+                taken: taken(14, 9, 31, 7),
+                camera_model: Some("Canon EOS R6".to_string()),
+                lens: Some("RF24-105mm F4 L IS USM".to_string()),
+                hash8: Some("b71e04d8".to_string()),
+            },
+        ),
+        (
+            "PXL_20240715_184402.jpg",
+            FileFacts {
+                original_name: "PXL_20240715_184402".to_string(),
+                ext: "jpg".to_string(),
+                taken: taken(15, 18, 44, 2),
+                camera_model: Some("Pixel 8".to_string()),
+                lens: None,
+                hash8: Some("0c5d7e92".to_string()),
+            },
+        ),
+    ]
+}
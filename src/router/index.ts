@@ -16,6 +16,12 @@ const routes: Array<RouteRecordRaw> = [
     component: () => import('../views/Import.vue'),
     meta: { titleKey: 'import.title' },
   },
+  {
+    path: '/history',
+    name: 'history',
+    component: () => import('../views/History.vue'),
+    meta: { titleKey: 'history.title' },
+  },
 ];
 
 const router = createRouter({
@@ -1,6 +1,8 @@
 import { defineStore } from 'pinia';
 import { ref, watch } from 'vue';
 
+export type ConflictPolicy = 'Rename' | 'KeepBoth' | 'Overwrite' | 'Skip';
+
 export const useSettingsStore = defineStore('settings', () => {
   const importDestination = ref<string | null>(
     localStorage.getItem('importDest'),
@@ -10,6 +12,21 @@ export const useSettingsStore = defineStore('settings', () => {
     localStorage.getItem('duplicateDest'),
   );
 
+  // null means the default layout of the backend
+  const pathTemplate = ref<string | null>(localStorage.getItem('pathTemplate'));
+
+  const conflictPolicy = ref<ConflictPolicy>(
+    (localStorage.getItem('conflictPolicy') as ConflictPolicy | null) ?? 'Rename',
+  );
+
+  const verifyCopies = ref(localStorage.getItem('verifyCopies') === 'true');
+
+  const onlyNewImports = ref(localStorage.getItem('onlyNewImports') !== 'false');
+
+  const duplicateReferences = ref<string[]>(
+    JSON.parse(localStorage.getItem('duplicateRefs') ?? '[]'),
+  );
+
   watch(importDestination, (val) => {
     if (val) {
       localStorage.setItem('importDest', val);
@@ -26,6 +43,38 @@ export const useSettingsStore = defineStore('settings', () => {
     }
   });
 
+  watch(pathTemplate, (val) => {
+    if (val) {
+      localStorage.setItem('pathTemplate', val);
+    } else {
+      localStorage.removeItem('pathTemplate');
+    }
+  });
+
+  watch(conflictPolicy, (val) => {
+    localStorage.setItem('conflictPolicy', val);
+  });
+
+  watch(verifyCopies, (val) => {
+    localStorage.setItem('verifyCopies', String(val));
+  });
+
+  watch(onlyNewImports, (val) => {
+    localStorage.setItem('onlyNewImports', String(val));
+  });
+
+  watch(
+    duplicateReferences,
+    (val) => {
+      if (val.length) {
+        localStorage.setItem('duplicateRefs', JSON.stringify(val));
+      } else {
+        localStorage.removeItem('duplicateRefs');
+      }
+    },
+    { deep: true },
+  );
+
   function setImportDestination(path: string | null) {
     importDestination.value = path;
   }
@@ -34,10 +83,34 @@ export const useSettingsStore = defineStore('settings', () => {
     duplicateDestination.value = path;
   }
 
+  function setPathTemplate(template: string | null) {
+    pathTemplate.value = template;
+  }
+
+  function addDuplicateReference(path: string) {
+    if (!duplicateReferences.value.includes(path)) {
+      duplicateReferences.value.push(path);
+    }
+  }
+
+  function removeDuplicateReference(path: string) {
+    duplicateReferences.value = duplicateReferences.value.filter(
+      (p) => p !== path,
+    );
+  }
+
   return {
     importDestination,
     setImportDestination,
     duplicateDestination,
     setDuplicateDestination,
+    pathTemplate,
+    setPathTemplate,
+    conflictPolicy,
+    verifyCopies,
+    onlyNewImports,
+    duplicateReferences,
+    addDuplicateReference,
+    removeDuplicateReference,
   };
 });